@@ -0,0 +1,3 @@
+fn main() {
+	std::process::exit(ultunnel_desktop_lib::cli::run());
+}
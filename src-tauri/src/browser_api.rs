@@ -1,29 +1,65 @@
+use crate::browser_pairing;
+use crate::browser_pairing::PairingStatus;
+use crate::domains;
+use crate::events::AppEvent;
+use crate::openapi;
+use crate::pac;
+use crate::settings::LocalSettings;
 use crate::AppState;
 use crate::SplitRoutingSettings;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::rejection::QueryRejection;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::http::header;
 use axum::http::HeaderMap;
+use axum::http::Method;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
 use axum::response::Response;
 use axum::routing::get;
 use axum::routing::post;
 use axum::Json;
 use axum::Router;
+use futures_util::stream;
+use futures_util::StreamExt;
 use serde::Deserialize;
 use serde::Serialize;
-use std::net::SocketAddr;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use tauri::AppHandle;
+use tauri::Emitter;
+use tauri::Manager;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tower_http::cors::AllowOrigin;
 use tower_http::cors::CorsLayer;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 
-const BROWSER_API_BIND: &str = "127.0.0.1:18491";
-const BROWSER_API_TOKEN: &str = "ultunnel-local-secret";
+const BROWSER_API_HOST: &str = "127.0.0.1";
+/// Файл в папке данных с фактическим адресом API (на случай запасного порта)
+const DISCOVERY_FILE: &str = "browser-api.json";
+
+/// Состояние сервера для UI: слушает ли он и на каком порту
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserApiStatus {
+    pub listening: bool,
+    pub port: Option<u16>,
+    pub configured_port: u16,
+    // настроенный порт занят, сервер поднят на свободном
+    pub fallback: bool,
+    pub error: Option<String>,
+}
 
 #[derive(Clone)]
 pub struct BrowserApiState {
+    pub app: AppHandle,
     pub app_state: Arc<AppState>,
 }
 
@@ -35,6 +71,17 @@ struct ApiResponse<T: Serialize> {
     error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<T>,
+    // ошибки валидации по полям запроса
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+}
+
+#[derive(Debug, Serialize)]
+struct FieldError {
+    // путь к полю: domain, domains[2]
+    field: String,
+    code: &'static str,
+    message: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -46,6 +93,7 @@ struct BrowserStateResponse {
     domain: String,
     socks5_enabled: bool,
     selected_profile: Option<String>,
+    profile_override: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -65,6 +113,49 @@ pub struct StateQuery {
     pub domain: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SelectProfileRequest {
+    pub profile: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ConnectRequest {
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfilesResponse {
+    profiles: Vec<String>,
+    selected_profile: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    // EventSource не умеет передавать заголовки, поэтому токен можно передать в query
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PairRequest {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairStatusQuery {
+    pub request_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PairStatusResponse {
+    status: PairingStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
 fn ok<T: Serialize>(data: T) -> Response {
     (
         StatusCode::OK,
@@ -72,45 +163,68 @@ fn ok<T: Serialize>(data: T) -> Response {
             ok: true,
             error: None::<String>,
             data: Some(data),
+            fields: vec![],
         }),
     )
         .into_response()
 }
 
-fn ok_empty() -> Response {
+fn err(status: StatusCode, message: impl Into<String>) -> Response {
     (
-        StatusCode::OK,
+        status,
         Json(ApiResponse::<serde_json::Value> {
-            ok: true,
-            error: None,
+            ok: false,
+            error: Some(message.into()),
             data: None,
+            fields: vec![],
         }),
     )
         .into_response()
 }
 
-fn err(status: StatusCode, message: impl Into<String>) -> Response {
+fn invalid(fields: Vec<FieldError>) -> Response {
     (
-        status,
+        StatusCode::UNPROCESSABLE_ENTITY,
         Json(ApiResponse::<serde_json::Value> {
             ok: false,
-            error: Some(message.into()),
+            error: Some("validation failed".to_string()),
             data: None,
+            fields,
         }),
     )
         .into_response()
 }
 
-fn is_authorized(headers: &HeaderMap) -> bool {
-    let Some(value) = headers.get("authorization") else {
-        return false;
-    };
+/// Домен из запроса: punycode, без www.
+fn validate_domain(field: &str, raw: &str) -> Result<String, FieldError> {
+    match domains::normalize_hostname(raw) {
+        Ok(d) => Ok(normalize_domain(&d)),
+        Err(e) => Err(FieldError {
+            field: field.to_string(),
+            code: e.code(),
+            message: e.to_string(),
+        }),
+    }
+}
 
-    let Ok(value) = value.to_str() else {
+fn origin(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::ORIGIN)?.to_str().ok()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn is_authorized(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(token) = bearer_token(headers) else {
         return false;
     };
 
-    value == format!("Bearer {}", BROWSER_API_TOKEN)
+    browser_pairing::authorize(state, token, origin(headers))
 }
 
 fn normalize_domain(domain: &str) -> String {
@@ -122,6 +236,15 @@ fn normalize_domain(domain: &str) -> String {
     }
 }
 
+/// Запись списка доменов: обычные домены нормализуются, записи с префиксом (regex: и т.п.) — нет
+fn normalize_entry(entry: &str) -> String {
+    if domains::has_rule_prefix(entry) {
+        entry.trim().to_string()
+    } else {
+        normalize_domain(entry)
+    }
+}
+
 fn normalize_domains(domains: &[String]) -> Vec<String> {
 	let mut result: Vec<String> = domains
 		.iter()
@@ -134,6 +257,39 @@ fn normalize_domains(domains: &[String]) -> Vec<String> {
 	result
 }
 
+/// Переключение, которое не подействует из-за переопределений выбранного профиля:
+/// `domains` — Some((домены, включить)) для /domain/toggle, None — для /tunnel-all
+fn shadowed_by_override(
+    settings: &LocalSettings,
+    domains: Option<(&[String], bool)>,
+) -> Option<String> {
+    let profile = settings.selected_config.as_deref()?;
+    let o = settings.active_routing_override()?;
+    let listed = |list: &[String]| {
+        domains
+            .iter()
+            .flat_map(|(ds, _)| ds.iter())
+            .find(|d| list.iter().any(|e| normalize_domain(e) == **d))
+            .cloned()
+    };
+
+    match (domains, o.enabled) {
+        (None, Some(_)) => Some(format!(
+            "routing mode is set by the override of profile {}",
+            profile
+        )),
+        (None, None) => None,
+        (Some(_), Some(false)) => Some(format!(
+            "profile {} sends all traffic through the tunnel",
+            profile
+        )),
+        (Some((_, true)), _) => listed(&o.bypass_domains)
+            .map(|d| format!("{} is bypassed by the override of profile {}", d, profile)),
+        (Some((_, false)), _) => listed(&o.proxy_domains)
+            .map(|d| format!("{} is proxied by the override of profile {}", d, profile)),
+    }
+}
+
 fn split_contains_domain(split: &SplitRoutingSettings, domain: &str) -> bool {
     let domain = normalize_domain(domain);
     split
@@ -149,14 +305,14 @@ fn add_domains_to_proxy_list(split: &mut SplitRoutingSettings, domains: &[String
 	split.proxy_domains = split
 		.proxy_domains
 		.iter()
-		.map(|d| normalize_domain(d))
+		.map(|d| normalize_entry(d))
 		.filter(|d| !d.is_empty())
 		.collect();
 
 	split.bypass_domains = split
 		.bypass_domains
 		.iter()
-		.map(|d| normalize_domain(d))
+		.map(|d| normalize_entry(d))
 		.filter(|d| !d.is_empty() && !domains.iter().any(|x| x == d))
 		.collect();
 
@@ -178,7 +334,7 @@ fn remove_domains_from_proxy_list(split: &mut SplitRoutingSettings, domains: &[S
 	split.proxy_domains = split
 		.proxy_domains
 		.iter()
-		.map(|d| normalize_domain(d))
+		.map(|d| normalize_entry(d))
 		.filter(|d| !d.is_empty() && !domains.iter().any(|x| x == d))
 		.collect();
 
@@ -186,21 +342,88 @@ fn remove_domains_from_proxy_list(split: &mut SplitRoutingSettings, domains: &[S
 	split.proxy_domains.dedup();
 }
 
+/// Результат изменения маршрутизации из расширения
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RoutingApplied {
+    // настройки действительно изменились и сохранены
+    changed: bool,
+    running: bool,
+    // запущенное ядро уже работает с новыми правилами
+    live: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    apply_error: Option<String>,
+}
+
+/// Меняет и сохраняет настройки, затем перезапускает ядро, если оно запущено
+async fn save_and_apply(
+    api: &BrowserApiState,
+    update: impl FnOnce(&mut LocalSettings),
+) -> Result<RoutingApplied, Response> {
+    let changed = {
+        let mut settings = api.app_state.settings.lock().unwrap();
+        let before = (settings.split_routing.clone(), settings.socks5_inbound);
+        update(&mut settings);
+
+        let changed = before != (settings.split_routing.clone(), settings.socks5_inbound);
+        if changed {
+            if let Err(e) = settings.save(&api.app_state.settings_path) {
+                error!("Ошибка сохранения config.json: {}", e);
+                return Err(err(StatusCode::INTERNAL_SERVER_ERROR, e));
+            }
+        }
+        changed
+    };
+
+    if !changed {
+        let running = crate::is_platform_running(&api.app_state);
+        return Ok(RoutingApplied {
+            changed,
+            running,
+            live: running,
+            apply_error: None,
+        });
+    }
+
+    crate::publish_routing(&api.app_state);
+
+    let (live, apply_error) = match crate::apply_routing_change(api.app.clone(), &api.app_state).await {
+        Ok(live) => (live, None),
+        Err(e) => {
+            error!("Не удалось применить настройки маршрутизации: {}", e);
+            (false, Some(e))
+        }
+    };
+
+    Ok(RoutingApplied {
+        changed,
+        running: crate::is_platform_running(&api.app_state),
+        live,
+        apply_error,
+    })
+}
+
 async fn get_state_handler(
     State(api): State<BrowserApiState>,
     headers: HeaderMap,
-    Query(query): Query<StateQuery>,
+    query: Result<Query<StateQuery>, QueryRejection>,
 ) -> Response {
-    if !is_authorized(&headers) {
+    if !is_authorized(&api.app_state, &headers) {
         return err(StatusCode::UNAUTHORIZED, "unauthorized");
     }
 
-    let domain = normalize_domain(&query.domain);
-    if domain.is_empty() {
-        return err(StatusCode::BAD_REQUEST, "domain is required");
-    }
+    let query = match query {
+        Ok(Query(v)) => v,
+        Err(r) => return err(r.status(), r.body_text()),
+    };
+    let domain = match validate_domain("domain", &query.domain) {
+        Ok(v) => v,
+        Err(e) => return invalid(vec![e]),
+    };
 
     let settings = api.app_state.settings.lock().unwrap().clone();
+    // как в конфиге ядра: с переопределениями выбранного профиля
+    let split = settings.active_split_routing();
 
     let resp = BrowserStateResponse {
         #[cfg(target_os = "windows")]
@@ -208,10 +431,11 @@ async fn get_state_handler(
         #[cfg(not(target_os = "windows"))]
         running: api.app_state.running.load(std::sync::atomic::Ordering::Relaxed),
 
-        site_enabled: split_contains_domain(&settings.split_routing, &domain),
-        tunnel_all: !settings.split_routing.enabled,
+        site_enabled: split_contains_domain(&split, &domain),
+        tunnel_all: !split.enabled,
         domain,
         socks5_enabled: settings.socks5_inbound,
+        profile_override: settings.active_routing_override().is_some(),
         selected_profile: settings.selected_config,
     };
 
@@ -221,28 +445,43 @@ async fn get_state_handler(
 async fn toggle_domain_handler(
 	State(api): State<BrowserApiState>,
 	headers: HeaderMap,
-	Json(body): Json<ToggleDomainRequest>,
+	body: Result<Json<ToggleDomainRequest>, JsonRejection>,
 ) -> Response {
-	if !is_authorized(&headers) {
+	if !is_authorized(&api.app_state, &headers) {
 		return err(StatusCode::UNAUTHORIZED, "unauthorized");
 	}
 
-	let domain = normalize_domain(&body.domain);
-	if domain.is_empty() {
-		return err(StatusCode::BAD_REQUEST, "domain is required");
-	}
+	let body = match body {
+		Ok(Json(v)) => v,
+		Err(r) => return err(r.status(), r.body_text()),
+	};
 
-	let mut domains = body.domains.unwrap_or_default();
-	domains.push(domain.clone());
+	let mut fields = vec![];
+	let mut domains = vec![];
+	match validate_domain("domain", &body.domain) {
+		Ok(d) => domains.push(d),
+		Err(e) => fields.push(e),
+	}
+	for (i, d) in body.domains.iter().flatten().enumerate() {
+		match validate_domain(&format!("domains[{}]", i), d) {
+			Ok(d) => domains.push(d),
+			Err(e) => fields.push(e),
+		}
+	}
+	if !fields.is_empty() {
+		return invalid(fields);
+	}
 	let domains = normalize_domains(&domains);
 
-	if domains.is_empty() {
-		return err(StatusCode::BAD_REQUEST, "domains are required");
+	let shadowed = {
+		let settings = api.app_state.settings.lock().unwrap();
+		shadowed_by_override(&settings, Some((&domains, body.enabled)))
+	};
+	if let Some(reason) = shadowed {
+		return err(StatusCode::CONFLICT, reason);
 	}
 
-	{
-		let mut settings = api.app_state.settings.lock().unwrap();
-
+	let applied = match save_and_apply(&api, |settings| {
 		settings.socks5_inbound = true;
 		settings.split_routing.enabled = true;
 
@@ -251,82 +490,419 @@ async fn toggle_domain_handler(
 		} else {
 			remove_domains_from_proxy_list(&mut settings.split_routing, &domains);
 		}
-
-		if let Err(e) = settings.save(&api.app_state.settings_path) {
-			error!("Ошибка сохранения config.json: {}", e);
-			return err(StatusCode::INTERNAL_SERVER_ERROR, e);
-		}
-	}
+	})
+	.await
+	{
+		Ok(v) => v,
+		Err(resp) => return resp,
+	};
 
 	ok(serde_json::json!({
         "domains": domains,
-        "count": domains.len()
+        "count": domains.len(),
+        "changed": applied.changed,
+        "running": applied.running,
+        "live": applied.live,
+        "applyError": applied.apply_error,
     }))
 }
 
 async fn tunnel_all_handler(
 	State(api): State<BrowserApiState>,
 	headers: HeaderMap,
-	Json(body): Json<TunnelAllRequest>,
+	body: Result<Json<TunnelAllRequest>, JsonRejection>,
 ) -> Response {
-	if !is_authorized(&headers) {
+	if !is_authorized(&api.app_state, &headers) {
 		return err(StatusCode::UNAUTHORIZED, "unauthorized");
 	}
 
-	{
-		let mut settings = api.app_state.settings.lock().unwrap();
+	let body = match body {
+		Ok(Json(v)) => v,
+		Err(r) => return err(r.status(), r.body_text()),
+	};
+
+	let shadowed = shadowed_by_override(&api.app_state.settings.lock().unwrap(), None);
+	if let Some(reason) = shadowed {
+		return err(StatusCode::CONFLICT, reason);
+	}
 
+	match save_and_apply(&api, |settings| {
 		settings.socks5_inbound = true;
 		settings.split_routing.enabled = !body.enabled;
-
-		if let Err(e) = settings.save(&api.app_state.settings_path) {
-			error!("Ошибка сохранения config.json: {}", e);
-			return err(StatusCode::INTERNAL_SERVER_ERROR, e);
-		}
+	})
+	.await
+	{
+		Ok(applied) => ok(applied),
+		Err(resp) => resp,
 	}
+}
+
+async fn profiles_handler(State(api): State<BrowserApiState>, headers: HeaderMap) -> Response {
+    if !is_authorized(&api.app_state, &headers) {
+        return err(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    ok(ProfilesResponse {
+        profiles: crate::profile_names(&api.app_state),
+        selected_profile: api.app_state.settings.lock().unwrap().selected_config.clone(),
+    })
+}
+
+async fn select_profile_handler(
+    State(api): State<BrowserApiState>,
+    headers: HeaderMap,
+    body: Result<Json<SelectProfileRequest>, JsonRejection>,
+) -> Response {
+    if !is_authorized(&api.app_state, &headers) {
+        return err(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    let body = match body {
+        Ok(Json(v)) => v,
+        Err(r) => return err(r.status(), r.body_text()),
+    };
+    let profile = body.profile.trim().to_string();
+    if profile.is_empty() {
+        return invalid(vec![FieldError {
+            field: "profile".to_string(),
+            code: "required",
+            message: "profile is required".to_string(),
+        }]);
+    }
+
+    match crate::select_profile(&api.app_state, profile) {
+        Ok(()) => ok(crate::status_snapshot(&api.app_state)),
+        Err(e) => err(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+async fn connect_handler(
+    State(api): State<BrowserApiState>,
+    headers: HeaderMap,
+    body: Option<Json<ConnectRequest>>,
+) -> Response {
+    if !is_authorized(&api.app_state, &headers) {
+        return err(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    if let Some(profile) = body.profile.filter(|p| !p.trim().is_empty()) {
+        if let Err(e) = crate::select_profile(&api.app_state, profile) {
+            return err(StatusCode::BAD_REQUEST, e);
+        }
+    }
+
+    match crate::connect_requested(api.app.clone(), &api.app_state).await {
+        Ok(()) => ok(crate::status_snapshot(&api.app_state)),
+        Err(e) => {
+            error!("Browser API: подключение не удалось: {}", e);
+            err(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+async fn disconnect_handler(State(api): State<BrowserApiState>, headers: HeaderMap) -> Response {
+    if !is_authorized(&api.app_state, &headers) {
+        return err(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    match crate::disconnect(api.app.clone(), &api.app_state).await {
+        Ok(()) => ok(crate::status_snapshot(&api.app_state)),
+        Err(e) => {
+            error!("Browser API: отключение не удалось: {}", e);
+            err(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+async fn stats_handler(State(api): State<BrowserApiState>, headers: HeaderMap) -> Response {
+    if !is_authorized(&api.app_state, &headers) {
+        return err(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    match crate::dashboard_stats(&api.app_state).await {
+        Ok(stats) => ok(stats),
+        Err(e) => err(StatusCode::BAD_GATEWAY, e),
+    }
+}
+
+/// Поток событий (SSE): сразу текущие state и routing, дальше — каждое изменение
+async fn events_handler(
+    State(api): State<BrowserApiState>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Response {
+    let token = bearer_token(&headers).or(query.token.as_deref());
+    let authorized = token.is_some_and(|t| browser_pairing::authorize(&api.app_state, t, origin(&headers)));
+    if !authorized {
+        return err(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    // подписываемся до снимка, чтобы не потерять изменение между ними
+    let rx = api.app_state.events.subscribe();
+    let initial = vec![
+        AppEvent::State(crate::status_snapshot(&api.app_state)),
+        AppEvent::Routing(crate::routing_snapshot(&api.app_state)),
+    ];
+
+    let updates = stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Browser API: подписчик /events пропустил {} событий", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter(initial)
+        .chain(updates)
+        .map(|event| Event::default().event(event.name()).json_data(event.data()));
+
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// Запрос PAC со страницы (<script src>, fetch) или через DNS rebinding.
+/// Системный и браузерный резолвер прокси ходят на 127.0.0.1/localhost
+/// и не присылают ни Origin, ни Sec-Fetch-*
+fn is_page_request(headers: &HeaderMap, port: u16) -> bool {
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    let local_host = host.is_some_and(|h| {
+        h == format!("{}:{}", BROWSER_API_HOST, port) || h == format!("localhost:{}", port)
+    });
 
-	ok_empty()
+    !local_host
+        || headers.contains_key(header::ORIGIN)
+        || headers.contains_key("sec-fetch-site")
+        || headers.contains_key("sec-fetch-mode")
+}
+
+/// PAC-файл без авторизации: браузер скачивает его сам, не передавая заголовков.
+/// Страницам он не отдаётся, иначе любой сайт прочитал бы списки доменов
+async fn pac_handler(State(api): State<BrowserApiState>, headers: HeaderMap) -> Response {
+    let port = api.app_state.browser_api_status.lock().unwrap().port;
+    // порт ещё не записан в статус — сервер только поднимается
+    if port.is_none_or(|port| is_page_request(&headers, port)) {
+        return err(StatusCode::FORBIDDEN, "PAC is not available to web pages");
+    }
+
+    let pac = {
+        let settings = api.app_state.settings.lock().unwrap();
+        pac::render(&settings)
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-ns-proxy-autoconfig"),
+            // списки доменов меняются, браузер должен перечитывать файл
+            (header::CACHE_CONTROL, "no-cache"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        pac,
+    )
+        .into_response()
+}
+
+async fn openapi_handler(State(api): State<BrowserApiState>) -> Response {
+    let port = api.app_state.browser_api_status.lock().unwrap().port;
+    let port = port.unwrap_or_else(|| api.app_state.settings.lock().unwrap().browser_api.port);
+    Json(openapi::document(port)).into_response()
 }
 
 async fn health_handler() -> Response {
 	ok(serde_json::json!({ "status": "ok" }))
 }
 
-pub fn spawn_browser_api(app_state: Arc<AppState>) {
-    tauri::async_runtime::spawn(async move {
-        let state = BrowserApiState { app_state };
-
-        let app = Router::new()
-            .route("/health", get(health_handler))
-            .route("/state", get(get_state_handler))
-            .route("/domain/toggle", post(toggle_domain_handler))
-            .route("/tunnel-all", post(tunnel_all_handler))
-            .layer(CorsLayer::permissive())
-            .with_state(state);
-
-        let addr: SocketAddr = match BROWSER_API_BIND.parse() {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Некорректный адрес Browser API: {}", e);
-                return;
-            }
-        };
-
-        let listener = match tokio::net::TcpListener::bind(addr).await {
-            Ok(v) => v,
-            Err(e) => {
-                error!(
-                    "Не удалось поднять Browser API на {}: {}",
-                    BROWSER_API_BIND, e
-                );
-                return;
+/// Запрос токена: только со страницы расширения, выдаётся после подтверждения в приложении
+async fn pair_request_handler(
+    State(api): State<BrowserApiState>,
+    headers: HeaderMap,
+    body: Option<Json<PairRequest>>,
+) -> Response {
+    let Some(origin) = origin(&headers).filter(|o| browser_pairing::is_extension_origin(o)) else {
+        return err(StatusCode::FORBIDDEN, "pairing is allowed only for browser extensions");
+    };
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+
+    let request = match api.app_state.pairing_requests.lock().unwrap().create(origin, &body.name) {
+        Ok(v) => v,
+        Err(e) => return err(StatusCode::TOO_MANY_REQUESTS, e),
+    };
+    info!("Запрос на сопряжение от {} (код {})", request.origin, request.code);
+
+    if let Some(win) = api.app.get_webview_window("main") {
+        let _ = win.show();
+        let _ = win.set_focus();
+    }
+    if let Err(e) = api.app.emit("browser-pairing-request", &request) {
+        warn!("Не удалось отправить browser-pairing-request: {}", e);
+    }
+
+    ok(request)
+}
+
+async fn pair_status_handler(
+    State(api): State<BrowserApiState>,
+    headers: HeaderMap,
+    query: Result<Query<PairStatusQuery>, QueryRejection>,
+) -> Response {
+    let query = match query {
+        Ok(Query(v)) => v,
+        Err(r) => return err(r.status(), r.body_text()),
+    };
+    let Some(origin) = origin(&headers) else {
+        return err(StatusCode::FORBIDDEN, "origin is required");
+    };
+
+    let polled = api
+        .app_state
+        .pairing_requests
+        .lock()
+        .unwrap()
+        .poll(&query.request_id, origin);
+
+    match polled {
+        Some((status, token)) => ok(PairStatusResponse { status, token }),
+        None => err(StatusCode::NOT_FOUND, "pairing request not found"),
+    }
+}
+
+/// CORS: запросы на сопряжение — от любого расширения, остальное — только от сопряжённых
+fn cors_layer(app_state: Arc<AppState>) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, parts| {
+            let Ok(origin) = origin.to_str() else {
+                return false;
+            };
+            if parts.uri.path().starts_with("/pair/") {
+                return browser_pairing::is_extension_origin(origin);
             }
-        };
+            browser_pairing::is_paired_origin(&app_state, origin)
+        }))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+}
+
+fn router(app: AppHandle, app_state: Arc<AppState>) -> Router {
+    let cors = cors_layer(app_state.clone());
+    let state = BrowserApiState { app, app_state };
+
+    Router::new()
+        .route("/health", get(health_handler))
+        .route("/openapi.json", get(openapi_handler))
+        .route("/proxy.pac", get(pac_handler))
+        .route("/pair/request", post(pair_request_handler))
+        .route("/pair/status", get(pair_status_handler))
+        .route("/state", get(get_state_handler))
+        .route("/events", get(events_handler))
+        .route("/domain/toggle", post(toggle_domain_handler))
+        .route("/tunnel-all", post(tunnel_all_handler))
+        .route("/profiles", get(profiles_handler))
+        .route("/profile/select", post(select_profile_handler))
+        .route("/connect", post(connect_handler))
+        .route("/disconnect", post(disconnect_handler))
+        .route("/stats", get(stats_handler))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// Настроенный порт, а если он занят — любой свободный
+async fn bind(port: u16) -> Result<(TcpListener, Option<String>), String> {
+    match TcpListener::bind((BROWSER_API_HOST, port)).await {
+        Ok(listener) => Ok((listener, None)),
+        Err(e) => {
+            warn!("Порт {} для Browser API недоступен ({}), берём свободный", port, e);
+            let listener = TcpListener::bind((BROWSER_API_HOST, 0))
+                .await
+                .map_err(|e2| format!("Порт {} недоступен: {}; запасной порт: {}", port, e, e2))?;
+            Ok((listener, Some(format!("Порт {} недоступен: {}", port, e))))
+        }
+    }
+}
 
-        info!("Browser API слушает http://{}", BROWSER_API_BIND);
+fn write_discovery_file(state: &AppState, port: u16) {
+    let path = state.data_dir.join(DISCOVERY_FILE);
+    let body = serde_json::json!({
+        "port": port,
+        "url": format!("http://{}:{}", BROWSER_API_HOST, port),
+        "pid": std::process::id(),
+    });
+
+    let r = serde_json::to_string_pretty(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(&path, s).map_err(|e| e.to_string()));
+    if let Err(e) = r {
+        warn!("Не удалось записать {}: {}", path.display(), e);
+    }
+}
+
+fn set_status(app: &AppHandle, state: &AppState, status: BrowserApiStatus) {
+    *state.browser_api_status.lock().unwrap() = status.clone();
+    if let Err(e) = app.emit("browser-api-status", status) {
+        warn!("Не удалось отправить browser-api-status: {}", e);
+    }
+}
+
+/// Сервер живёт, пока не придёт `browser_api_restart` (смена порта, повтор после ошибки)
+pub fn spawn_browser_api(app: AppHandle, app_state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let port = app_state.settings.lock().unwrap().browser_api.port;
+
+            match bind(port).await {
+                Ok((listener, warning)) => {
+                    let actual = listener.local_addr().map(|a| a.port()).unwrap_or(port);
+                    info!("Browser API слушает http://{}:{}", BROWSER_API_HOST, actual);
+                    write_discovery_file(&app_state, actual);
+                    set_status(
+                        &app,
+                        &app_state,
+                        BrowserApiStatus {
+                            listening: true,
+                            port: Some(actual),
+                            configured_port: port,
+                            fallback: warning.is_some(),
+                            error: warning,
+                        },
+                    );
+
+                    tokio::select! {
+                        r = axum::serve(listener, router(app.clone(), app_state.clone())) => {
+                            let e = match r {
+                                Ok(()) => "сервер остановлен".to_string(),
+                                Err(e) => e.to_string(),
+                            };
+                            error!("Browser API завершился с ошибкой: {}", e);
+                            set_status(&app, &app_state, BrowserApiStatus {
+                                configured_port: port,
+                                error: Some(e),
+                                ..Default::default()
+                            });
+                        }
+                        _ = app_state.browser_api_restart.notified() => {
+                            info!("Перезапуск Browser API");
+                            continue;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Не удалось поднять Browser API: {}", e);
+                    set_status(
+                        &app,
+                        &app_state,
+                        BrowserApiStatus {
+                            configured_port: port,
+                            error: Some(e),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
 
-        if let Err(e) = axum::serve(listener, app).await {
-            error!("Browser API завершился с ошибкой: {}", e);
+            // после ошибки ждём, пока пользователь сменит порт или попросит повторить
+            app_state.browser_api_restart.notified().await;
         }
     });
 }
@@ -0,0 +1,284 @@
+// Сопряжение браузерного расширения с приложением: расширение присылает запрос,
+// пользователь подтверждает его в окне приложения, и расширение получает собственный
+// случайный токен. Токены хранятся в config.json (browserApi.pairings); отзыв помечает
+// запись revokedAt, такие токены больше не принимаются.
+
+use crate::settings::BrowserPairing;
+use crate::AppState;
+use chrono::Local;
+use rand::Rng;
+use serde::Serialize;
+use std::time::Duration;
+use std::time::Instant;
+use tracing::info;
+
+/// Сколько запрос ждёт подтверждения пользователя
+const REQUEST_TTL: Duration = Duration::from_secs(120);
+/// Ожидающих запросов одновременно (чтобы страница не завалила UI окнами подтверждения)
+const MAX_PENDING: usize = 4;
+
+const EXTENSION_SCHEMES: [&str; 3] = [
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PairingStatus {
+    Pending,
+    Approved,
+    Denied,
+    Expired,
+}
+
+#[derive(Debug)]
+struct PairingRequest {
+    id: String,
+    origin: String,
+    name: String,
+    // короткий код, который показывают и расширение, и приложение
+    code: String,
+    created: Instant,
+    status: PairingStatus,
+    token: Option<String>,
+}
+
+/// Запрос на сопряжение в том виде, в каком его видят UI и расширение
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingRequestView {
+    pub id: String,
+    pub origin: String,
+    pub name: String,
+    pub code: String,
+    pub expires_in_secs: u64,
+}
+
+/// Сопряжение без токена — для списка в настройках
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingView {
+    pub id: String,
+    pub origin: String,
+    pub name: String,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+}
+
+/// Запросы, ожидающие ответа пользователя (только в памяти)
+#[derive(Debug, Default)]
+pub struct PairingRequests {
+    items: Vec<PairingRequest>,
+}
+
+impl PairingRequest {
+    fn view(&self) -> PairingRequestView {
+        PairingRequestView {
+            id: self.id.clone(),
+            origin: self.origin.clone(),
+            name: self.name.clone(),
+            code: self.code.clone(),
+            expires_in_secs: REQUEST_TTL.saturating_sub(self.created.elapsed()).as_secs(),
+        }
+    }
+}
+
+impl PairingRequests {
+    fn prune(&mut self) {
+        // завершённые запросы держим ещё один TTL, чтобы расширение успело узнать результат
+        self.items.retain(|r| r.created.elapsed() < REQUEST_TTL * 2);
+        for r in &mut self.items {
+            if r.status == PairingStatus::Pending && r.created.elapsed() >= REQUEST_TTL {
+                r.status = PairingStatus::Expired;
+            }
+        }
+    }
+
+    pub fn create(&mut self, origin: &str, name: &str) -> Result<PairingRequestView, String> {
+        self.prune();
+        // повторный запрос того же расширения заменяет предыдущий
+        self.items
+            .retain(|r| !(r.origin == origin && r.status == PairingStatus::Pending));
+
+        let pending = self
+            .items
+            .iter()
+            .filter(|r| r.status == PairingStatus::Pending)
+            .count();
+        if pending >= MAX_PENDING {
+            return Err("too many pending pairing requests".to_string());
+        }
+
+        let request = PairingRequest {
+            id: random_hex(16),
+            origin: origin.to_string(),
+            name: name.trim().chars().take(64).collect(),
+            code: format!("{:06}", rand::thread_rng().gen_range(0..1_000_000)),
+            created: Instant::now(),
+            status: PairingStatus::Pending,
+            token: None,
+        };
+        let view = request.view();
+        self.items.push(request);
+        Ok(view)
+    }
+
+    pub fn pending(&mut self) -> Vec<PairingRequestView> {
+        self.prune();
+        self.items
+            .iter()
+            .filter(|r| r.status == PairingStatus::Pending)
+            .map(|r| r.view())
+            .collect()
+    }
+
+    /// Статус запроса для расширения. Токен отдаётся один раз, после ответа запрос забывается
+    pub fn poll(&mut self, id: &str, origin: &str) -> Option<(PairingStatus, Option<String>)> {
+        self.prune();
+        let idx = self
+            .items
+            .iter()
+            .position(|r| r.id == id && r.origin == origin)?;
+
+        let status = self.items[idx].status;
+        if status == PairingStatus::Pending {
+            return Some((status, None));
+        }
+        Some((status, self.items.remove(idx).token))
+    }
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+fn now() -> String {
+    Local::now().to_rfc3339()
+}
+
+/// Сравнение за время, не зависящее от позиции первого несовпадения
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Origin страницы расширения: chrome-extension://<id>, moz-extension://<uuid>, ...
+pub fn is_extension_origin(origin: &str) -> bool {
+    EXTENSION_SCHEMES.iter().any(|scheme| {
+        origin
+            .strip_prefix(scheme)
+            .is_some_and(|id| !id.is_empty() && !id.contains('/'))
+    })
+}
+
+pub fn is_paired_origin(state: &AppState, origin: &str) -> bool {
+    state
+        .settings
+        .lock()
+        .unwrap()
+        .browser_api
+        .pairings
+        .iter()
+        .any(|p| p.revoked_at.is_none() && p.origin == origin)
+}
+
+/// Действующий токен; Origin, если браузер его прислал, должен совпадать с сопряжённым
+pub fn authorize(state: &AppState, token: &str, origin: Option<&str>) -> bool {
+    state
+        .settings
+        .lock()
+        .unwrap()
+        .browser_api
+        .pairings
+        .iter()
+        .filter(|p| p.revoked_at.is_none())
+        .any(|p| {
+            constant_time_eq(p.token.as_bytes(), token.as_bytes())
+                && origin.iter().all(|o| *o == p.origin)
+        })
+}
+
+pub fn list(state: &AppState) -> Vec<PairingView> {
+    state
+        .settings
+        .lock()
+        .unwrap()
+        .browser_api
+        .pairings
+        .iter()
+        .map(|p| PairingView {
+            id: p.id.clone(),
+            origin: p.origin.clone(),
+            name: p.name.clone(),
+            created_at: p.created_at.clone(),
+            revoked_at: p.revoked_at.clone(),
+        })
+        .collect()
+}
+
+/// Ответ пользователя на запрос. При подтверждении расширение получает новый токен,
+/// прежние токены того же расширения отзываются
+pub fn confirm(state: &AppState, request_id: &str, approve: bool) -> Result<(), String> {
+    let mut requests = state.pairing_requests.lock().unwrap();
+    requests.prune();
+
+    let request = requests
+        .items
+        .iter_mut()
+        .find(|r| r.id == request_id)
+        .ok_or("Запрос на сопряжение не найден")?;
+    if request.status != PairingStatus::Pending {
+        return Err("Запрос на сопряжение уже неактуален".to_string());
+    }
+
+    if !approve {
+        request.status = PairingStatus::Denied;
+        info!("Сопряжение с {} отклонено", request.origin);
+        return Ok(());
+    }
+
+    let pairing = BrowserPairing {
+        id: random_hex(8),
+        origin: request.origin.clone(),
+        name: request.name.clone(),
+        token: random_hex(32),
+        created_at: now(),
+        revoked_at: None,
+    };
+
+    {
+        let mut s = state.settings.lock().unwrap();
+        for p in s
+            .browser_api
+            .pairings
+            .iter_mut()
+            .filter(|p| p.origin == pairing.origin && p.revoked_at.is_none())
+        {
+            p.revoked_at = Some(pairing.created_at.clone());
+        }
+        s.browser_api.pairings.push(pairing.clone());
+        s.save(&state.settings_path)?;
+    }
+
+    request.status = PairingStatus::Approved;
+    request.token = Some(pairing.token);
+    info!("Браузерное расширение {} сопряжено", pairing.origin);
+    Ok(())
+}
+
+pub fn revoke(state: &AppState, id: &str) -> Result<(), String> {
+    let mut s = state.settings.lock().unwrap();
+    let pairing = s
+        .browser_api
+        .pairings
+        .iter_mut()
+        .find(|p| p.id == id)
+        .ok_or("Сопряжение не найдено")?;
+
+    if pairing.revoked_at.is_none() {
+        pairing.revoked_at = Some(now());
+        info!("Токен расширения {} отозван", pairing.origin);
+    }
+    s.save(&state.settings_path)
+}
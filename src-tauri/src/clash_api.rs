@@ -0,0 +1,141 @@
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Адрес и секрет Clash API, которые прописываются в singbox.json (см. ensure_clash_api)
+pub const CLASH_API_ADDR: &str = "127.0.0.1:9090";
+pub const CLASH_API_SECRET: &str = "ultunnel-local-secret";
+
+pub fn url(path: &str) -> String {
+    format!("http://{}{}", CLASH_API_ADDR, path)
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionResponse {
+    version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DelayResponse {
+    delay: Option<u64>,
+    message: Option<String>,
+}
+
+/// Версия ядра из `/version`; ошибка — Clash API недоступен
+pub async fn version(client: &reqwest::Client) -> Result<Option<String>, String> {
+    let resp = client
+        .get(url("/version"))
+        .bearer_auth(CLASH_API_SECRET)
+        .send()
+        .await
+        .map_err(|e| format!("version request failed: {}", e))?;
+
+    Ok(resp.json::<VersionResponse>().await.ok().and_then(|v| v.version))
+}
+
+/// Ожидание, пока только что запущенное ядро поднимет Clash API
+pub async fn wait_ready(timeout_ms: u64) -> bool {
+    let client = reqwest::Client::new();
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+
+    while Instant::now() < deadline {
+        let ok = client
+            .get(url("/version"))
+            .bearer_auth(CLASH_API_SECRET)
+            .timeout(Duration::from_millis(1000))
+            .send()
+            .await
+            .map(|r| r.status().is_success())
+            .unwrap_or(false);
+
+        if ok {
+            return true;
+        }
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+    }
+
+    false
+}
+
+/// Проверка outbound через `/proxies/{name}/delay`: задержка в мс или ошибка
+pub async fn probe_delay(
+    client: &reqwest::Client,
+    outbound: &str,
+    test_url: &str,
+    timeout_ms: u64,
+) -> Result<u64, String> {
+    let resp = client
+        .get(url(&format!("/proxies/{}/delay", outbound)))
+        .query(&[("url", test_url), ("timeout", &timeout_ms.to_string())])
+        .bearer_auth(CLASH_API_SECRET)
+        .timeout(Duration::from_millis(timeout_ms + 2000))
+        .send()
+        .await
+        .map_err(|e| format!("delay request failed: {}", e))?;
+
+    let status = resp.status();
+    let body = resp
+        .json::<DelayResponse>()
+        .await
+        .map_err(|e| format!("delay response parse failed: {}", e))?;
+
+    match body.delay {
+        Some(ms) if status.is_success() && ms > 0 => Ok(ms),
+        _ => Err(body
+            .message
+            .unwrap_or_else(|| format!("delay returned HTTP {}", status))),
+    }
+}
+
+/// Потоковый ответ Clash API (`/traffic`, `/memory`, ...): по одному JSON-объекту на строку.
+pub struct JsonLines {
+    stream: BoxStream<'static, Result<Vec<u8>, String>>,
+    buffer: Vec<u8>,
+}
+
+impl JsonLines {
+    pub async fn open(client: &reqwest::Client, path: &str) -> Result<Self, String> {
+        let resp = client
+            .get(url(path))
+            .bearer_auth(CLASH_API_SECRET)
+            .send()
+            .await
+            .map_err(|e| format!("{} request failed: {}", path, e))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("{} returned HTTP {}", path, resp.status()));
+        }
+
+        Ok(Self {
+            stream: resp
+                .bytes_stream()
+                .map(|r| r.map(|b| b.to_vec()).map_err(|e| e.to_string()))
+                .boxed(),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Следующая строка потока. `None` — поток закрыт ядром.
+    pub async fn next<T: DeserializeOwned>(&mut self) -> Option<Result<T, String>> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                return Some(serde_json::from_str(line).map_err(|e| format!("parse failed: {}", e)));
+            }
+
+            match self.stream.next().await? {
+                Ok(chunk) => self.buffer.extend_from_slice(&chunk),
+                Err(e) => return Some(Err(format!("stream read failed: {}", e))),
+            }
+        }
+    }
+}
@@ -0,0 +1,513 @@
+// CLI `ultunnel` для машин без графической оболочки. Работает с теми же config.json
+// и configs.json, что и приложение, и использует тот же код генерации конфига и запуска ядра.
+
+use crate::api::ProxyConfig;
+use crate::clash_api;
+#[cfg(target_os = "linux")]
+use crate::daemon;
+#[cfg(target_os = "linux")]
+use crate::kill_switch;
+use crate::profiles;
+use crate::settings::LocalSettings;
+use crate::singbox_config;
+#[cfg(target_os = "linux")]
+use crate::systemd;
+use crate::tunnel;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::Level;
+
+const USAGE: &str = "\
+Использование: ultunnel [--data-dir <путь>] [-v] <команда> [аргументы]
+
+Команды:
+  login <access-key>   сохранить ключ доступа и загрузить профили
+  refresh              обновить список профилей с сервера
+  list                 показать профили (* — выбранный)
+  select <профиль>     выбрать профиль по имени или номеру из list
+  connect [профиль]    подключиться (ядро продолжит работать в фоне)
+  disconnect           отключиться
+  status               состояние подключения
+  check                проверить все профили (внешний IP через каждый)
+
+Linux, systemd:
+  daemon                         работать как сервис (Type=notify, sd_notify/watchdog)
+  service print [--user]         показать unit-файл
+  service install [--user] [--enable]
+                                 установить unit-файл (--enable — включить и запустить)
+  service uninstall [--user]     остановить и удалить unit-файл
+
+Папка данных по умолчанию — та же, что у приложения; переопределяется
+--data-dir или переменной ULTUNNEL_DATA_DIR. На Linux приложение работает
+от root, поэтому для общих настроек CLI тоже запускается через sudo.";
+
+enum Command {
+    Login(String),
+    Refresh,
+    List,
+    Select(String),
+    Connect(Option<String>),
+    Disconnect,
+    Status,
+    Check,
+    Daemon,
+    Service(ServiceAction, ServiceOptions),
+}
+
+enum ServiceAction {
+    Print,
+    Install,
+    Uninstall,
+}
+
+struct ServiceOptions {
+    user: bool,
+    enable: bool,
+}
+
+fn parse_service(action: &str, flags: &[&str]) -> Result<Command, String> {
+    let action = match action {
+        "print" => ServiceAction::Print,
+        "install" => ServiceAction::Install,
+        "uninstall" => ServiceAction::Uninstall,
+        _ => return Err(format!("неизвестное действие service: {}", action)),
+    };
+
+    let mut options = ServiceOptions {
+        user: false,
+        enable: false,
+    };
+    for flag in flags {
+        match *flag {
+            "--user" => options.user = true,
+            "--enable" => options.enable = true,
+            _ => return Err(format!("неизвестный флаг service: {}", flag)),
+        }
+    }
+
+    Ok(Command::Service(action, options))
+}
+
+struct Options {
+    data_dir: Option<PathBuf>,
+    verbose: bool,
+    command: Command,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut data_dir = None;
+    let mut verbose = false;
+    let mut rest = Vec::new();
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--data-dir" => {
+                let v = it.next().ok_or("--data-dir: не указан путь")?;
+                data_dir = Some(PathBuf::from(v));
+            }
+            "-v" | "--verbose" => verbose = true,
+            _ => rest.push(arg.as_str()),
+        }
+    }
+
+    let command = match rest.as_slice() {
+        ["login", key] => Command::Login(key.to_string()),
+        ["refresh"] => Command::Refresh,
+        ["list"] => Command::List,
+        ["select", profile] => Command::Select(profile.to_string()),
+        ["connect"] => Command::Connect(None),
+        ["connect", profile] => Command::Connect(Some(profile.to_string())),
+        ["disconnect"] => Command::Disconnect,
+        ["status"] => Command::Status,
+        ["check"] => Command::Check,
+        ["daemon"] => Command::Daemon,
+        ["service", action, flags @ ..] => parse_service(action, flags)?,
+        [] => return Err("не указана команда".into()),
+        [cmd, ..] => return Err(format!("неизвестная команда или аргументы: {}", cmd)),
+    };
+
+    Ok(Options {
+        data_dir,
+        verbose,
+        command,
+    })
+}
+
+fn init_stderr_logger(verbose: bool) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(if verbose { Level::INFO } else { Level::WARN })
+        .with_target(false)
+        .finish();
+    let _ = tracing::subscriber::set_global_default(subscriber);
+}
+
+/// Точка входа бинарника `ultunnel`, возвращает код выхода
+pub fn run() -> i32 {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help" || a == "help") {
+        println!("{USAGE}");
+        return 0;
+    }
+
+    let options = match parse_args(&args) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ultunnel: {e}\n\n{USAGE}");
+            return 2;
+        }
+    };
+    init_stderr_logger(options.verbose);
+
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ultunnel: {e}");
+            return 1;
+        }
+    };
+
+    let result = runtime.block_on(async {
+        let data_dir = match options.data_dir {
+            Some(v) => v,
+            None => tunnel::default_data_dir()?,
+        };
+        let mut cli = Cli::open(data_dir);
+        cli.execute(options.command).await
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("ultunnel: {e}");
+            1
+        }
+    }
+}
+
+struct Cli {
+    data_dir: PathBuf,
+    settings_path: PathBuf,
+    settings: LocalSettings,
+}
+
+impl Cli {
+    fn open(data_dir: PathBuf) -> Self {
+        let settings_path = tunnel::settings_path(&data_dir);
+        let settings = LocalSettings::load(&settings_path);
+        Self {
+            data_dir,
+            settings_path,
+            settings,
+        }
+    }
+
+    fn configs_path(&self) -> PathBuf {
+        profiles::configs_path_from_settings(&self.settings_path)
+    }
+
+    fn configs(&self) -> Vec<ProxyConfig> {
+        profiles::load_configs_from_file(&self.configs_path())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        self.settings.save(&self.settings_path)
+    }
+
+    /// Удаляет переопределения маршрутизации профилей, которых больше нет в списке
+    fn forget_missing_profiles(&mut self, configs: &[ProxyConfig]) -> bool {
+        let names: Vec<String> = configs.iter().map(|c| c.name.clone()).collect();
+        self.settings.retain_profile_routing(&names)
+    }
+
+    async fn execute(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Login(key) => self.login(key).await,
+            Command::Refresh => self.refresh().await,
+            Command::List => self.list(),
+            Command::Select(profile) => self.select(&profile),
+            Command::Connect(profile) => self.connect(profile).await,
+            Command::Disconnect => self.disconnect().await,
+            Command::Status => self.status().await,
+            Command::Check => self.check().await,
+            Command::Daemon => self.daemon().await,
+            Command::Service(action, options) => self.service(action, options),
+        }
+    }
+
+    async fn login(&mut self, key: String) -> Result<(), String> {
+        let key = key.trim().to_string();
+        let configs = profiles::fetch_configs(&key).await?;
+        profiles::save_configs_to_file(&self.configs_path(), &configs)?;
+
+        self.settings.access_key = key;
+        self.forget_missing_profiles(&configs);
+        self.save()?;
+
+        println!("Ключ доступа сохранён, профилей: {}", configs.len());
+        self.print_profiles(&configs);
+        Ok(())
+    }
+
+    async fn refresh(&mut self) -> Result<(), String> {
+        let configs = profiles::fetch_configs(&self.settings.access_key).await?;
+        profiles::save_configs_to_file(&self.configs_path(), &configs)?;
+        if self.forget_missing_profiles(&configs) {
+            self.save()?;
+        }
+
+        println!("Профилей: {}", configs.len());
+        self.print_profiles(&configs);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<(), String> {
+        let configs = self.configs();
+        if configs.is_empty() {
+            println!("Профили не загружены, выполните `ultunnel refresh`");
+            return Ok(());
+        }
+        self.print_profiles(&configs);
+        Ok(())
+    }
+
+    fn print_profiles(&self, configs: &[ProxyConfig]) {
+        let selected = self.settings.selected_config.as_deref();
+        for (i, c) in configs.iter().enumerate() {
+            let mark = if Some(c.name.as_str()) == selected { '*' } else { ' ' };
+            println!("{} {:>2}. {}", mark, i + 1, c.name);
+        }
+    }
+
+    /// Профиль по имени или по номеру из `list`
+    fn resolve_profile(&self, query: &str) -> Result<ProxyConfig, String> {
+        let configs = self.configs();
+        if let Some(c) = profiles::find_config(&configs, query) {
+            return Ok(c.clone());
+        }
+
+        query
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| configs.get(i))
+            .cloned()
+            .ok_or_else(|| format!("Профиль не найден: {} (см. `ultunnel list`)", query))
+    }
+
+    fn select(&mut self, query: &str) -> Result<(), String> {
+        let cfg = self.resolve_profile(query)?;
+        self.settings.selected_config = Some(cfg.name.clone());
+        self.save()?;
+
+        println!("Выбран профиль: {}", cfg.name);
+        if tunnel::running_pid(&self.data_dir).is_some() {
+            println!("Чтобы применить, переподключитесь: ultunnel connect");
+        }
+        Ok(())
+    }
+
+    fn selected_profile(&self) -> Result<ProxyConfig, String> {
+        let selected = self
+            .settings
+            .selected_config
+            .clone()
+            .ok_or("Не выбран конфиг (ultunnel select <профиль>)")?;
+        profiles::find_config(&self.configs(), &selected)
+            .cloned()
+            .ok_or_else(|| "Выбранный конфиг не найден (ultunnel refresh)".to_string())
+    }
+
+    async fn connect(&mut self, profile: Option<String>) -> Result<(), String> {
+        require_root()?;
+
+        if let Some(query) = profile {
+            let cfg = self.resolve_profile(&query)?;
+            self.settings.selected_config = Some(cfg.name);
+        }
+        let cfg = self.selected_profile()?;
+
+        // повторный connect перезапускает ядро, чтобы применить профиль и настройки
+        tunnel::stop_singbox_async(&self.data_dir).await?;
+
+        self.settings.connected_intent = true;
+        self.save()?;
+
+        match tunnel::start(&self.data_dir, &cfg, &self.settings).await {
+            Ok(child) => {
+                // процесс остаётся работать после выхода из CLI
+                println!("Подключено: {} (pid {})", cfg.name, child.id());
+                Ok(())
+            }
+            Err(e) => {
+                let _ = self.disconnect_quietly().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        require_root()?;
+
+        if self.disconnect_quietly().await? {
+            println!("Отключено");
+        } else {
+            println!("sing-box не был запущен");
+        }
+        Ok(())
+    }
+
+    /// Явное отключение, как в приложении: ядро, намерение «подключён» и kill switch
+    async fn disconnect_quietly(&mut self) -> Result<bool, String> {
+        let stopped = tunnel::stop_singbox_async(&self.data_dir).await?;
+
+        self.settings.connected_intent = false;
+        self.save()?;
+
+        #[cfg(target_os = "linux")]
+        if self.settings.kill_switch_enabled {
+            kill_switch::disable()?;
+        }
+
+        Ok(stopped)
+    }
+
+    async fn status(&self) -> Result<(), String> {
+        println!(
+            "Профиль: {}",
+            self.settings.selected_config.as_deref().unwrap_or("не выбран")
+        );
+
+        let Some(pid) = tunnel::running_pid(&self.data_dir) else {
+            println!("Состояние: отключено");
+            return Ok(());
+        };
+        println!("Состояние: подключено (pid {})", pid);
+
+        match clash_api::version(&reqwest::Client::new()).await {
+            Ok(version) => println!("Ядро: sing-box {}", version.as_deref().unwrap_or("?")),
+            Err(e) => println!("Clash API недоступен: {}", e),
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.settings.kill_switch_enabled {
+            let active = if kill_switch::is_active() { "активен" } else { "не активен" };
+            println!("Kill switch: {}", active);
+        }
+
+        Ok(())
+    }
+
+    async fn check(&mut self) -> Result<(), String> {
+        require_root()?;
+
+        let configs = self.configs();
+        if configs.is_empty() {
+            return Err("Профили не загружены, выполните `ultunnel refresh`".into());
+        }
+
+        let was_running = tunnel::stop_singbox_async(&self.data_dir).await?;
+        let total = configs.len();
+        let mut ok = 0;
+
+        for (idx, cfg) in configs.iter().enumerate() {
+            let check = async {
+                let cfg_path = singbox_config::write_singbox_config_for_profile_check(
+                    &self.data_dir,
+                    &cfg.config,
+                    &self.settings,
+                )?;
+                let _child = tunnel::launch(&self.data_dir, &cfg_path).await?;
+                tokio::time::sleep(Duration::from_millis(2500)).await;
+                tunnel::fetch_external_ip().await
+            }
+            .await;
+
+            match check {
+                Ok(ip) => {
+                    ok += 1;
+                    println!("[{}/{}] {}: {}", idx + 1, total, cfg.name, ip);
+                }
+                Err(e) => println!("[{}/{}] {}: ошибка: {}", idx + 1, total, cfg.name, e),
+            }
+
+            tunnel::stop_singbox_async(&self.data_dir).await?;
+        }
+
+        println!("Работают {} из {}", ok, total);
+
+        if was_running {
+            let cfg = self.selected_profile()?;
+            tunnel::start(&self.data_dir, &cfg, &self.settings).await?;
+            println!("Подключение к {} восстановлено", cfg.name);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Cli {
+    async fn daemon(&mut self) -> Result<(), String> {
+        daemon::run(&self.data_dir).await
+    }
+
+    fn service(&self, action: ServiceAction, options: ServiceOptions) -> Result<(), String> {
+        match action {
+            ServiceAction::Print => {
+                let cli = systemd::cli_binary()?;
+                print!("{}", systemd::render_unit(options.user, &cli, &self.data_dir));
+            }
+            ServiceAction::Install => {
+                if !options.user {
+                    require_root()?;
+                }
+                let path = systemd::install_unit(options.user, &self.data_dir, options.enable)?;
+                println!("Unit-файл установлен: {}", path.display());
+                if !options.enable {
+                    let scope = if options.user { "--user " } else { "" };
+                    println!("Запуск: systemctl {}enable --now {}", scope, systemd::UNIT_NAME);
+                }
+            }
+            ServiceAction::Uninstall => {
+                if !options.user {
+                    require_root()?;
+                }
+                let path = systemd::uninstall_unit(options.user)?;
+                println!("Unit-файл удалён: {}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Cli {
+    async fn daemon(&mut self) -> Result<(), String> {
+        Err("daemon поддержан только на Linux".into())
+    }
+
+    fn service(&self, _action: ServiceAction, _options: ServiceOptions) -> Result<(), String> {
+        Err("service поддержан только на Linux (systemd)".into())
+    }
+}
+
+/// TUN и nftables требуют root; без него sing-box упадёт с невнятной ошибкой
+#[cfg(target_os = "linux")]
+fn require_root() -> Result<(), String> {
+    if tunnel::is_root() {
+        Ok(())
+    } else {
+        Err("нужны права root (запустите через sudo)".into())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn require_root() -> Result<(), String> {
+    Ok(())
+}
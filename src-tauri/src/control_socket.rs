@@ -0,0 +1,248 @@
+// Локальное управление из скриптов: JSON-RPC 2.0 через Unix-сокет в runtime-папке пользователя.
+// Один запрос/ответ на строку. Доступ ограничен правами на файл сокета (0600, владелец —
+// пользователь, запустивший приложение, даже если оно перезапущено через pkexec).
+//
+// Методы: status, profiles, selectProfile {profile}, connect {profile?}, disconnect, subscribe.
+// После subscribe сервер присылает уведомления {"method":"state"|"routing","params":{...}}.
+
+use crate::events::AppEvent;
+use crate::AppState;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tauri::AppHandle;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tokio::sync::broadcast;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+const SOCKET_NAME: &str = "ultunnel.sock";
+
+// коды ошибок JSON-RPC 2.0
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const APP_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProfileParams {
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+/// $XDG_RUNTIME_DIR/ultunnel.sock, без runtime-папки — рядом с настройками
+fn socket_path(state: &AppState) -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute() && p.is_dir())
+        .unwrap_or_else(|| state.data_dir.clone())
+        .join(SOCKET_NAME)
+}
+
+/// Пользователь, от имени которого запущено приложение до повышения прав
+fn invoking_uid() -> Option<libc::uid_t> {
+    if unsafe { libc::geteuid() } != 0 {
+        return None;
+    }
+    ["PKEXEC_UID", "SUDO_UID"]
+        .iter()
+        .find_map(|k| env::var(k).ok()?.parse::<libc::uid_t>().ok())
+}
+
+fn restrict_permissions(path: &Path) -> Result<(), String> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
+
+    if let Some(uid) = invoking_uid() {
+        let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+        // gid не меняем (-1)
+        if unsafe { libc::chown(c_path.as_ptr(), uid, libc::gid_t::MAX) } != 0 {
+            return Err(format!("chown: {}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+async fn bind(path: &Path) -> Result<UnixListener, String> {
+    if path.exists() {
+        // живой сервер на этом сокете — второй экземпляр не запускаем
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(format!("{} уже используется", path.display()));
+        }
+        fs::remove_file(path).map_err(|e| e.to_string())?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| e.to_string())?;
+    restrict_permissions(path)?;
+    Ok(listener)
+}
+
+pub fn spawn_control_socket(app: AppHandle, state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        let path = socket_path(&state);
+        let listener = match bind(&path).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Control socket {} недоступен: {}", path.display(), e);
+                return;
+            }
+        };
+        info!("Control socket слушает {}", path.display());
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let app = app.clone();
+                    let state = state.clone();
+                    tauri::async_runtime::spawn(async move {
+                        if let Err(e) = serve_client(stream, app, state).await {
+                            warn!("Control socket: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Control socket accept: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+}
+
+async fn serve_client(stream: UnixStream, app: AppHandle, state: Arc<AppState>) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events: Option<broadcast::Receiver<AppEvent>> = None;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line.map_err(|e| e.to_string())? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(response) = handle_line(&line, &app, &state, &mut events).await {
+                    write_message(&mut writer, &response).await?;
+                }
+            }
+            event = next_event(&mut events) => match event {
+                Ok(event) => {
+                    let message = json!({ "jsonrpc": "2.0", "method": event.name(), "params": event.data() });
+                    write_message(&mut writer, &message).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Control socket: подписчик пропустил {} событий", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => events = None,
+            },
+        }
+    }
+}
+
+async fn next_event(
+    events: &mut Option<broadcast::Receiver<AppEvent>>,
+) -> Result<AppEvent, broadcast::error::RecvError> {
+    match events {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn write_message(writer: &mut tokio::net::unix::OwnedWriteHalf, message: &Value) -> Result<(), String> {
+    let mut bytes = serde_json::to_vec(message).map_err(|e| e.to_string())?;
+    bytes.push(b'\n');
+    writer.write_all(&bytes).await.map_err(|e| e.to_string())
+}
+
+/// Ответ на строку запроса; `None` — уведомление без id, ответ не нужен
+async fn handle_line(
+    line: &str,
+    app: &AppHandle,
+    state: &Arc<AppState>,
+    events: &mut Option<broadcast::Receiver<AppEvent>>,
+) -> Option<Value> {
+    let request = match serde_json::from_str::<RpcRequest>(line) {
+        Ok(v) => v,
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+    };
+    let id = request.id.clone();
+
+    let result = dispatch(&request, app, state, events).await;
+    let id = id?;
+
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error_response(id, code, message),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn profile_params(params: &Value) -> Result<ProfileParams, (i64, String)> {
+    if params.is_null() {
+        return Ok(ProfileParams::default());
+    }
+    serde_json::from_value(params.clone()).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+async fn dispatch(
+    request: &RpcRequest,
+    app: &AppHandle,
+    state: &Arc<AppState>,
+    events: &mut Option<broadcast::Receiver<AppEvent>>,
+) -> Result<Value, (i64, String)> {
+    let app_err = |e: String| (APP_ERROR, e);
+
+    match request.method.as_str() {
+        "status" => {}
+        "profiles" => return Ok(json!(crate::profile_names(state))),
+        "selectProfile" => {
+            let profile = profile_params(&request.params)?
+                .profile
+                .ok_or((INVALID_PARAMS, "profile не указан".to_string()))?;
+            crate::select_profile(state, profile).map_err(app_err)?;
+        }
+        "connect" => {
+            if let Some(profile) = profile_params(&request.params)?.profile {
+                crate::select_profile(state, profile).map_err(app_err)?;
+            }
+            crate::connect_requested(app.clone(), state).await.map_err(app_err)?;
+        }
+        "disconnect" => {
+            crate::disconnect(app.clone(), state).await.map_err(app_err)?;
+        }
+        "subscribe" => {
+            if events.is_none() {
+                *events = Some(state.events.subscribe());
+            }
+        }
+        other => return Err((METHOD_NOT_FOUND, format!("Неизвестный метод: {}", other))),
+    }
+
+    Ok(json!(crate::status_snapshot(state)))
+}
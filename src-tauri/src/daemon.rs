@@ -0,0 +1,104 @@
+// `ultunnel daemon`: туннель в foreground-процессе под управлением systemd.
+// READY отправляется после того, как поднялся Clash API; пинги watchdog идут,
+// только пока ядро живо и отвечает; SIGTERM/SIGINT — штатная остановка.
+// Если sing-box падает, процесс завершается с ошибкой, и systemd перезапускает
+// сервис (правила kill switch при этом остаются на месте).
+
+use crate::clash_api;
+use crate::kill_switch;
+use crate::profiles;
+use crate::settings::LocalSettings;
+use crate::systemd;
+use crate::tunnel;
+use std::path::Path;
+use std::process::Child;
+use std::time::Duration;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Проверка живости без watchdog (вне systemd или без WatchdogSec)
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub async fn run(data_dir: &Path) -> Result<(), String> {
+    let settings_path = tunnel::settings_path(data_dir);
+    let mut settings = LocalSettings::load(&settings_path);
+    let configs =
+        profiles::load_configs_from_file(&profiles::configs_path_from_settings(&settings_path));
+
+    let selected = settings
+        .selected_config
+        .clone()
+        .ok_or("Не выбран конфиг (ultunnel select <профиль>)")?;
+    let cfg = profiles::find_config(&configs, &selected)
+        .cloned()
+        .ok_or("Выбранный конфиг не найден (ultunnel refresh)")?;
+
+    let mut sigterm = signal(SignalKind::terminate()).map_err(|e| e.to_string())?;
+    let mut sigint = signal(SignalKind::interrupt()).map_err(|e| e.to_string())?;
+
+    // ядро могло остаться от предыдущего экземпляра, упавшего без остановки
+    tunnel::stop_singbox_async(data_dir).await?;
+
+    settings.connected_intent = true;
+    settings.save(&settings_path)?;
+
+    systemd::notify(&format!("STATUS=Подключение к {}", cfg.name));
+    let mut child = tunnel::start(data_dir, &cfg, &settings).await?;
+    info!("Сервис запущен, профиль {}", cfg.name);
+    systemd::notify(&format!("READY=1\nSTATUS=Подключено: {}", cfg.name));
+
+    let watchdog = systemd::watchdog_interval();
+    let mut ticker = tokio::time::interval(watchdog.unwrap_or(DEFAULT_PROBE_INTERVAL));
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let reason = loop {
+        tokio::select! {
+            _ = sigterm.recv() => break "SIGTERM",
+            _ = sigint.recv() => break "SIGINT",
+            _ = ticker.tick() => {
+                if let Ok(Some(status)) = child.try_wait() {
+                    error!("sing-box завершился: {}", status);
+                    systemd::notify("STATUS=sing-box завершился");
+                    return Err(format!("sing-box завершился: {}", status));
+                }
+
+                match clash_api::version(&client).await {
+                    Ok(_) if watchdog.is_some() => systemd::notify("WATCHDOG=1"),
+                    Ok(_) => {}
+                    // пинг пропускаем: если ядро не оживёт, systemd перезапустит сервис по WatchdogSec
+                    Err(e) => warn!("Clash API не отвечает: {}", e),
+                }
+            }
+        }
+    };
+
+    info!("Получен {}, остановка сервиса", reason);
+    systemd::notify("STOPPING=1");
+    stop(data_dir, &settings_path, &mut settings, child).await
+}
+
+async fn stop(
+    data_dir: &Path,
+    settings_path: &Path,
+    settings: &mut LocalSettings,
+    mut child: Child,
+) -> Result<(), String> {
+    tunnel::stop_singbox_async(data_dir).await?;
+    let _ = child.kill();
+    let _ = child.wait();
+
+    settings.connected_intent = false;
+    settings.save(settings_path)?;
+
+    if settings.kill_switch_enabled {
+        kill_switch::disable()?;
+    }
+    Ok(())
+}
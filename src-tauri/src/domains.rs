@@ -0,0 +1,286 @@
+// Проверка доменов из Browser API и настроек маршрутизации: IDN переводятся в punycode,
+// имя проверяется по правилам hostname (метки до 63 символов из a-z, 0-9 и дефиса, всего до 253).
+//
+// Записи списков доменов могут иметь префикс: full: (точное имя), suffix: (имя и поддомены,
+// по умолчанию), keyword: (подстрока), regex: (регулярное выражение).
+
+use crate::net_rules;
+use std::fmt;
+use std::net::IpAddr;
+
+const PREFIX_FULL: &str = "full:";
+const PREFIX_SUFFIX: &str = "suffix:";
+const PREFIX_KEYWORD: &str = "keyword:";
+const PREFIX_REGEX: &str = "regex:";
+
+const MAX_HOSTNAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostnameError {
+    Empty,
+    IpAddress,
+    Idna,
+    TooLong,
+    LabelTooLong(String),
+    InvalidLabel(String),
+}
+
+impl HostnameError {
+    /// Машиночитаемый код для ответов API
+    pub fn code(&self) -> &'static str {
+        match self {
+            HostnameError::Empty => "empty",
+            HostnameError::IpAddress => "ip_address",
+            HostnameError::Idna => "idna",
+            HostnameError::TooLong => "too_long",
+            HostnameError::LabelTooLong(_) => "label_too_long",
+            HostnameError::InvalidLabel(_) => "invalid_label",
+        }
+    }
+}
+
+impl fmt::Display for HostnameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostnameError::Empty => write!(f, "пустое имя"),
+            HostnameError::IpAddress => write!(f, "IP-адрес вместо домена"),
+            HostnameError::Idna => write!(f, "некорректное международное имя"),
+            HostnameError::TooLong => write!(f, "имя длиннее {} символов", MAX_HOSTNAME_LEN),
+            HostnameError::LabelTooLong(l) => {
+                write!(f, "часть «{}» длиннее {} символов", l, MAX_LABEL_LEN)
+            }
+            HostnameError::InvalidLabel(l) => write!(f, "недопустимая часть имени «{}»", l),
+        }
+    }
+}
+
+/// Домен в ASCII-форме (punycode для IDN), в нижнем регистре и без точки в конце
+pub fn normalize_hostname(input: &str) -> Result<String, HostnameError> {
+    let name = input.trim().trim_end_matches('.');
+    if name.is_empty() {
+        return Err(HostnameError::Empty);
+    }
+    if name.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return Err(HostnameError::IpAddress);
+    }
+
+    let ascii = idna::domain_to_ascii(name).map_err(|_| HostnameError::Idna)?;
+    if ascii.len() > MAX_HOSTNAME_LEN {
+        return Err(HostnameError::TooLong);
+    }
+
+    for label in ascii.split('.') {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(HostnameError::LabelTooLong(label.to_string()));
+        }
+        let valid = !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        if !valid {
+            return Err(HostnameError::InvalidLabel(label.to_string()));
+        }
+    }
+
+    Ok(ascii)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainMatch {
+    Full,
+    Suffix,
+    Keyword,
+    Regex,
+}
+
+impl DomainMatch {
+    /// Поле правила маршрутизации sing-box
+    pub fn rule_field(self) -> &'static str {
+        match self {
+            DomainMatch::Full => "domain",
+            DomainMatch::Suffix => "domain_suffix",
+            DomainMatch::Keyword => "domain_keyword",
+            DomainMatch::Regex => "domain_regex",
+        }
+    }
+
+    /// Запись списка для значения: суффикс без префикса, остальные с префиксом
+    pub fn entry(self, value: &str) -> String {
+        match self {
+            DomainMatch::Full => format!("{}{}", PREFIX_FULL, value),
+            DomainMatch::Suffix => value.to_string(),
+            DomainMatch::Keyword => format!("{}{}", PREFIX_KEYWORD, value),
+            DomainMatch::Regex => format!("{}{}", PREFIX_REGEX, value),
+        }
+    }
+}
+
+/// Тип записи и значение без префикса; запись без префикса — суффикс
+pub fn split_domain_rule(entry: &str) -> (DomainMatch, &str) {
+    let entry = entry.trim();
+    let prefixes = [
+        (PREFIX_FULL, DomainMatch::Full),
+        (PREFIX_SUFFIX, DomainMatch::Suffix),
+        (PREFIX_KEYWORD, DomainMatch::Keyword),
+        (PREFIX_REGEX, DomainMatch::Regex),
+    ];
+
+    for (prefix, kind) in prefixes {
+        if let Some(value) = entry.strip_prefix(prefix) {
+            return (kind, value.trim());
+        }
+    }
+    (DomainMatch::Suffix, entry)
+}
+
+/// Запись с явным префиксом (такие записи нельзя нормализовать как обычный домен)
+pub fn has_rule_prefix(entry: &str) -> bool {
+    let entry = entry.trim();
+    [PREFIX_FULL, PREFIX_SUFFIX, PREFIX_KEYWORD, PREFIX_REGEX]
+        .iter()
+        .any(|p| entry.starts_with(p))
+}
+
+/// Проверка записи при сохранении. Возвращает каноническую форму: домены в punycode,
+/// суффиксы без префикса (как записи, добавленные до появления префиксов)
+pub fn normalize_domain_rule(entry: &str) -> Result<String, String> {
+    let (kind, value) = split_domain_rule(entry);
+    match kind {
+        DomainMatch::Suffix => {
+            let value = value.trim_start_matches("*.").trim_start_matches('.');
+            normalize_hostname(value).map_err(|e| e.to_string())
+        }
+        DomainMatch::Full => {
+            let host = normalize_hostname(value).map_err(|e| e.to_string())?;
+            Ok(format!("{}{}", PREFIX_FULL, host))
+        }
+        DomainMatch::Keyword => {
+            if value.is_empty() || value.chars().any(char::is_whitespace) {
+                return Err("ключевое слово пустое или содержит пробелы".to_string());
+            }
+            Ok(format!("{}{}", PREFIX_KEYWORD, value.to_lowercase()))
+        }
+        DomainMatch::Regex => {
+            if value.is_empty() {
+                return Err("пустое регулярное выражение".to_string());
+            }
+            regex::Regex::new(value)
+                .map_err(|e| format!("некорректное регулярное выражение: {}", e))?;
+            Ok(format!("{}{}", PREFIX_REGEX, value))
+        }
+    }
+}
+
+/// Проверка списка целиком: сообщает обо всех ошибках сразу, убирает пустые записи и повторы
+pub fn normalize_domain_rules(entries: &[String]) -> Result<Vec<String>, String> {
+    net_rules::normalize_list(entries, normalize_domain_rule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_hostname_punycode_and_case() {
+        assert_eq!(normalize_hostname("Example.COM.").unwrap(), "example.com");
+        assert_eq!(
+            normalize_hostname("пример.рф").unwrap(),
+            "xn--e1afmkfd.xn--p1ai"
+        );
+        assert_eq!(normalize_hostname("").unwrap_err(), HostnameError::Empty);
+        assert_eq!(
+            normalize_hostname("10.0.0.1").unwrap_err(),
+            HostnameError::IpAddress
+        );
+        assert_eq!(
+            normalize_hostname("[::1]").unwrap_err(),
+            HostnameError::IpAddress
+        );
+        assert_eq!(
+            normalize_hostname("-bad.com").unwrap_err().code(),
+            "invalid_label"
+        );
+        let long = format!("{}.com", "a".repeat(64));
+        assert_eq!(
+            normalize_hostname(&long).unwrap_err().code(),
+            "label_too_long"
+        );
+    }
+
+    #[test]
+    fn split_domain_rule_prefixes() {
+        assert_eq!(
+            split_domain_rule("example.com"),
+            (DomainMatch::Suffix, "example.com")
+        );
+        assert_eq!(
+            split_domain_rule("suffix:example.com"),
+            (DomainMatch::Suffix, "example.com")
+        );
+        assert_eq!(
+            split_domain_rule(" full: example.com "),
+            (DomainMatch::Full, "example.com")
+        );
+        assert_eq!(
+            split_domain_rule("keyword:ads"),
+            (DomainMatch::Keyword, "ads")
+        );
+        assert_eq!(
+            split_domain_rule("regex:^a\\.b$"),
+            (DomainMatch::Regex, "^a\\.b$")
+        );
+    }
+
+    #[test]
+    fn normalize_domain_rule_canonical_forms() {
+        assert_eq!(
+            normalize_domain_rule("*.Example.com").unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            normalize_domain_rule(".example.com").unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            normalize_domain_rule("suffix:пример.рф").unwrap(),
+            "xn--e1afmkfd.xn--p1ai"
+        );
+        assert_eq!(
+            normalize_domain_rule("full:WWW.Example.com").unwrap(),
+            "full:www.example.com"
+        );
+        assert_eq!(normalize_domain_rule("keyword:Ads").unwrap(), "keyword:ads");
+        assert_eq!(
+            normalize_domain_rule("regex:^Ads\\.").unwrap(),
+            "regex:^Ads\\."
+        );
+    }
+
+    #[test]
+    fn normalize_domain_rule_rejects_invalid() {
+        assert!(normalize_domain_rule("regex:(").is_err());
+        assert!(normalize_domain_rule("regex:").is_err());
+        assert!(normalize_domain_rule("keyword:").is_err());
+        assert!(normalize_domain_rule("keyword:a b").is_err());
+        assert!(normalize_domain_rule("full:10.0.0.1").is_err());
+        assert!(normalize_domain_rule("bad_domain!.com").is_err());
+    }
+
+    #[test]
+    fn normalize_domain_rules_reports_all_errors_and_dedups() {
+        let entries = vec![
+            "example.com".to_string(),
+            "*.example.com".to_string(),
+            " ".to_string(),
+        ];
+        assert_eq!(
+            normalize_domain_rules(&entries).unwrap(),
+            vec!["example.com"]
+        );
+
+        let bad = vec!["regex:(".to_string(), "keyword:".to_string()];
+        let err = normalize_domain_rules(&bad).unwrap_err();
+        assert!(err.contains("regex:(") && err.contains("keyword:"));
+    }
+}
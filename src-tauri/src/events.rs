@@ -0,0 +1,58 @@
+// События состояния приложения для внешних подписчиков (control socket, поток /events Browser API)
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusSnapshot {
+    pub running: bool,
+    pub profile: Option<String>,
+    pub connected_intent: bool,
+}
+
+/// Раздельная маршрутизация в том виде, который нужен расширению
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingSnapshot {
+    pub enabled: bool,
+    pub tunnel_all: bool,
+    pub socks5_enabled: bool,
+    pub proxy_domains: Vec<String>,
+    pub bypass_domains: Vec<String>,
+    // у выбранного профиля есть переопределения, списки выше уже с ними
+    pub profile_override: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+pub enum AppEvent {
+    /// подключение и выбранный профиль
+    State(StatusSnapshot),
+    /// списки доменов и режим маршрутизации
+    Routing(RoutingSnapshot),
+}
+
+impl AppEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::State(_) => "state",
+            AppEvent::Routing(_) => "routing",
+        }
+    }
+
+    pub fn data(&self) -> Value {
+        let data = match self {
+            AppEvent::State(v) => serde_json::to_value(v),
+            AppEvent::Routing(v) => serde_json::to_value(v),
+        };
+        data.unwrap_or(Value::Null)
+    }
+}
+
+pub fn channel() -> broadcast::Sender<AppEvent> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
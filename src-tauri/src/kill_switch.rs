@@ -0,0 +1,395 @@
+// Kill switch для Linux: пока пользователь «подключён», весь исходящий трафик,
+// кроме loopback, TUN-интерфейса и адресов proxy-серверов, блокируется через nftables.
+// Правила снимаются только при явном отключении, поэтому падение sing-box
+// не приводит к утечке трафика мимо туннеля. DNS до системных резолверов и DHCP
+// разрешены, иначе ни ядро, ни приложение не смогут разрешить адреса серверов.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use tracing::info;
+use tracing::warn;
+
+const NFT_TABLE: &str = "ultunnel_killswitch";
+/// Имя TUN-интерфейса, если в конфиге оно не задано (нужно, чтобы разрешить его в правилах)
+const DEFAULT_TUN_INTERFACE: &str = "ultun0";
+/// Адреса серверов, разрешённые при первом включении: при перезапуске ядра правила
+/// уже стоят, и заново разрешать имена не нужно
+const SERVERS_CACHE_FILE: &str = "kill-switch-servers.json";
+/// resolv.conf системы и вышестоящие серверы systemd-resolved (сам он слушает 127.0.0.53)
+const RESOLV_CONF_PATHS: [&str; 2] = ["/etc/resolv.conf", "/run/systemd/resolve/resolv.conf"];
+
+/// Прописывает interface_name в TUN inbound, если его нет, и возвращает имена TUN-интерфейсов
+pub fn ensure_tun_interface_names(cfg: &mut Value) -> Vec<String> {
+    let mut names = Vec::new();
+
+    if let Some(inbounds) = cfg.get_mut("inbounds").and_then(|v| v.as_array_mut()) {
+        for ib in inbounds.iter_mut() {
+            if ib.get("type").and_then(|v| v.as_str()) != Some("tun") {
+                continue;
+            }
+            let Some(obj) = ib.as_object_mut() else {
+                continue;
+            };
+
+            let name = obj
+                .get("interface_name")
+                .and_then(|v| v.as_str())
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| {
+                    obj.insert(
+                        "interface_name".to_string(),
+                        Value::String(DEFAULT_TUN_INTERFACE.to_string()),
+                    );
+                    DEFAULT_TUN_INTERFACE.to_string()
+                });
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+/// Адреса серверов из outbounds/endpoints конфига (IP или доменные имена)
+pub fn collect_server_hosts(cfg: &Value) -> Vec<String> {
+    let mut hosts = BTreeSet::new();
+
+    let mut push = |v: Option<&Value>| {
+        if let Some(s) = v.and_then(|x| x.as_str()).map(|s| s.trim()) {
+            if !s.is_empty() {
+                hosts.insert(s.trim_matches(|c| c == '[' || c == ']').to_string());
+            }
+        }
+    };
+
+    for key in ["outbounds", "endpoints"] {
+        let Some(items) = cfg.get(key).and_then(|v| v.as_array()) else {
+            continue;
+        };
+        for item in items {
+            push(item.get("server"));
+            if let Some(peers) = item.get("peers").and_then(|v| v.as_array()) {
+                for peer in peers {
+                    push(peer.get("server"));
+                    push(peer.get("address"));
+                }
+            }
+        }
+    }
+
+    hosts.into_iter().collect()
+}
+
+/// Адреса серверов; имена, уже разрешённые ранее, берутся из `cache`, новые туда добавляются
+async fn resolve_hosts(
+    hosts: &[String],
+    cache: &mut BTreeMap<String, Vec<IpAddr>>,
+) -> Result<BTreeSet<IpAddr>, String> {
+    let mut out = BTreeSet::new();
+
+    for host in hosts {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            out.insert(ip);
+            continue;
+        }
+        if let Some(ips) = cache.get(host) {
+            out.extend(ips.iter().copied());
+            continue;
+        }
+
+        let addrs: Vec<IpAddr> = tokio::net::lookup_host(format!("{}:0", host))
+            .await
+            .map_err(|e| format!("Не удалось разрешить адрес сервера {}: {}", host, e))?
+            .map(|a| a.ip())
+            .collect();
+        out.extend(addrs.iter().copied());
+        cache.insert(host.clone(), addrs);
+    }
+
+    Ok(out)
+}
+
+fn load_servers_cache(path: &Path) -> BTreeMap<String, Vec<IpAddr>> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_servers_cache(path: &Path, cache: &BTreeMap<String, Vec<IpAddr>>) {
+    let result = serde_json::to_string_pretty(cache)
+        .map_err(|e| e.to_string())
+        .and_then(|s| fs::write(path, s).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        warn!("Не удалось сохранить адреса серверов kill switch: {}", e);
+    }
+}
+
+/// Адреса из строк `nameserver` (без зоны IPv6 вида fe80::1%eth0)
+fn parse_nameservers(resolv_conf: &str) -> BTreeSet<IpAddr> {
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .filter_map(|addr| addr.split('%').next()?.parse::<IpAddr>().ok())
+        .filter(|ip| !ip.is_loopback())
+        .collect()
+}
+
+fn system_resolvers() -> BTreeSet<IpAddr> {
+    RESOLV_CONF_PATHS
+        .iter()
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .flat_map(|s| parse_nameservers(&s))
+        .collect()
+}
+
+/// Адреса одного семейства через запятую для множества nft; None — адресов нет
+fn address_set(ips: &BTreeSet<IpAddr>, v4: bool) -> Option<String> {
+    let list: Vec<String> = ips
+        .iter()
+        .filter(|ip| ip.is_ipv4() == v4)
+        .map(|ip| ip.to_string())
+        .collect();
+    (!list.is_empty()).then(|| list.join(", "))
+}
+
+fn render_ruleset(
+    tun_interfaces: &[String],
+    servers: &BTreeSet<IpAddr>,
+    resolvers: &BTreeSet<IpAddr>,
+) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', ""));
+
+    let mut rules = vec![format!("oifname {} accept", quote("lo"))];
+    for name in tun_interfaces {
+        rules.push(format!("oifname {} accept", quote(name)));
+    }
+    for (family, v4) in [("ip", true), ("ip6", false)] {
+        if let Some(set) = address_set(servers, v4) {
+            rules.push(format!("{} daddr {{ {} }} accept", family, set));
+        }
+        if let Some(set) = address_set(resolvers, v4) {
+            for proto in ["udp", "tcp"] {
+                rules.push(format!(
+                    "{} daddr {{ {} }} {} dport 53 accept",
+                    family, set, proto
+                ));
+            }
+        }
+    }
+    // DHCP и DHCPv6: без них интерфейс потеряет адрес при продлении аренды
+    rules.push("udp sport 68 udp dport 67 accept".to_string());
+    rules.push("udp sport 546 udp dport 547 accept".to_string());
+
+    // add + delete + объявление — атомарная замена таблицы одним вызовом nft
+    format!(
+        "add table inet {table}\n\
+         delete table inet {table}\n\
+         table inet {table} {{\n\
+         \tchain output {{\n\
+         \t\ttype filter hook output priority 0; policy drop;\n\
+         \t\t{rules}\n\
+         \t}}\n\
+         }}\n",
+        table = NFT_TABLE,
+        rules = rules.join("\n\t\t"),
+    )
+}
+
+fn run_nft(script: &str) -> Result<(), String> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Не удалось запустить nft: {e}"))?;
+
+    child
+        .stdin
+        .take()
+        .ok_or("nft: stdin недоступен".to_string())?
+        .write_all(script.as_bytes())
+        .map_err(|e| format!("nft: ошибка записи правил: {e}"))?;
+
+    let out = child.wait_with_output().map_err(|e| e.to_string())?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "nft завершился с ошибкой: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))
+    }
+}
+
+/// Устанавливает (или обновляет) правила kill switch для конфига, который будет запущен.
+/// Доменные имена серверов разрешаются при первом включении; пока правила стоят
+/// (перезапуск ядра), используются те же адреса из кэша в папке данных.
+pub async fn enable(data_dir: &Path, cfg: &Value, tun_interfaces: &[String]) -> Result<(), String> {
+    if tun_interfaces.is_empty() {
+        return Err("Kill switch: в конфиге нет TUN inbound".into());
+    }
+
+    let cache_path = data_dir.join(SERVERS_CACHE_FILE);
+    let mut cache = if is_active() {
+        load_servers_cache(&cache_path)
+    } else {
+        BTreeMap::new()
+    };
+
+    let hosts = collect_server_hosts(cfg);
+    let servers = resolve_hosts(&hosts, &mut cache).await?;
+    if servers.is_empty() {
+        return Err("Kill switch: в конфиге не найдено адресов серверов".into());
+    }
+    save_servers_cache(&cache_path, &cache);
+
+    let resolvers = system_resolvers();
+    run_nft(&render_ruleset(tun_interfaces, &servers, &resolvers))?;
+    info!(
+        "Kill switch включён: tun={:?}, серверов={}",
+        tun_interfaces,
+        servers.len()
+    );
+    Ok(())
+}
+
+/// Снимает правила kill switch (если они были установлены)
+pub fn disable() -> Result<(), String> {
+    if !is_active() {
+        return Ok(());
+    }
+    run_nft(&format!("delete table inet {}\n", NFT_TABLE))?;
+    info!("Kill switch выключен");
+    Ok(())
+}
+
+pub fn is_active() -> bool {
+    Command::new("nft")
+        .args(["list", "table", "inet", NFT_TABLE])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or_else(|e| {
+            warn!("Не удалось выполнить nft: {}", e);
+            false
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn servers(list: &[&str]) -> BTreeSet<IpAddr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn ruleset_replaces_table_atomically_with_drop_policy() {
+        let script = render_ruleset(&["ultun0".to_string()], &servers(&[]), &servers(&[]));
+        assert!(script.starts_with(
+            "add table inet ultunnel_killswitch\ndelete table inet ultunnel_killswitch\n"
+        ));
+        assert!(script.contains("type filter hook output priority 0; policy drop;"));
+        assert!(script.contains("oifname \"lo\" accept"));
+        assert!(script.contains("oifname \"ultun0\" accept"));
+        // без серверов пустые множества адресов nft не принимает
+        assert!(!script.contains("daddr"));
+    }
+
+    #[test]
+    fn ruleset_splits_servers_by_family() {
+        let script = render_ruleset(
+            &["tun0".to_string(), "tun1".to_string()],
+            &servers(&["203.0.113.1", "198.51.100.2", "2001:db8::1"]),
+            &servers(&[]),
+        );
+        assert!(script.contains("oifname \"tun1\" accept"));
+        assert!(script.contains("ip daddr { 198.51.100.2, 203.0.113.1 } accept"));
+        assert!(script.contains("ip6 daddr { 2001:db8::1 } accept"));
+    }
+
+    #[test]
+    fn ruleset_strips_quotes_from_interface_names() {
+        let script = render_ruleset(&["tun\"0".to_string()], &servers(&[]), &servers(&[]));
+        assert!(script.contains("oifname \"tun0\" accept"));
+    }
+
+    #[test]
+    fn ruleset_allows_dns_to_resolvers_and_dhcp() {
+        let script = render_ruleset(
+            &["tun0".to_string()],
+            &servers(&["203.0.113.1"]),
+            &servers(&["192.168.1.1", "2001:db8::53"]),
+        );
+        assert!(script.contains("ip daddr { 192.168.1.1 } udp dport 53 accept"));
+        assert!(script.contains("ip daddr { 192.168.1.1 } tcp dport 53 accept"));
+        assert!(script.contains("ip6 daddr { 2001:db8::53 } udp dport 53 accept"));
+        assert!(script.contains("udp sport 68 udp dport 67 accept"));
+        assert!(script.contains("udp sport 546 udp dport 547 accept"));
+    }
+
+    #[test]
+    fn nameservers_skip_loopback_and_zone() {
+        let conf = "# comment\nnameserver 127.0.0.53\nnameserver 1.1.1.1\n\
+                    nameserver fe80::1%eth0\noptions edns0\nsearch lan\n";
+        assert_eq!(parse_nameservers(conf), servers(&["1.1.1.1", "fe80::1"]));
+    }
+
+    #[tokio::test]
+    async fn cached_hosts_are_not_resolved_again() {
+        let mut cache = BTreeMap::new();
+        cache.insert(
+            "vpn.invalid".to_string(),
+            vec!["203.0.113.5".parse::<IpAddr>().unwrap()],
+        );
+        let hosts = vec!["vpn.invalid".to_string(), "198.51.100.1".to_string()];
+        let resolved = resolve_hosts(&hosts, &mut cache).await.unwrap();
+        assert_eq!(resolved, servers(&["198.51.100.1", "203.0.113.5"]));
+    }
+
+    #[test]
+    fn tun_interface_name_is_filled_in() {
+        let mut cfg = json!({
+            "inbounds": [
+                { "type": "tun", "tag": "tun-in" },
+                { "type": "tun", "interface_name": "custom0" },
+                { "type": "socks", "tag": "socks-in" },
+            ]
+        });
+        assert_eq!(
+            ensure_tun_interface_names(&mut cfg),
+            vec!["ultun0", "custom0"]
+        );
+        assert_eq!(cfg["inbounds"][0]["interface_name"], "ultun0");
+        assert!(cfg["inbounds"][2].get("interface_name").is_none());
+    }
+
+    #[test]
+    fn server_hosts_from_outbounds_and_endpoints() {
+        let cfg = json!({
+            "outbounds": [
+                { "type": "vless", "server": "example.com" },
+                { "type": "direct" },
+            ],
+            "endpoints": [
+                { "type": "wireguard", "peers": [{ "address": "[2001:db8::1]" }] },
+            ],
+        });
+        assert_eq!(
+            collect_server_hosts(&cfg),
+            vec!["2001:db8::1", "example.com"]
+        );
+    }
+}
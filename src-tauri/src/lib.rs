@@ -1,21 +1,62 @@
 mod api;
 mod browser_api;
+mod browser_pairing;
+mod clash_api;
+pub mod cli;
+#[cfg(unix)]
+mod control_socket;
+#[cfg(target_os = "linux")]
+mod daemon;
+mod domains;
+mod events;
+#[cfg(target_os = "linux")]
+mod kill_switch;
 #[cfg(target_os = "macos")]
 mod macos_smjobbless;
+mod net_rules;
+#[cfg(target_os = "linux")]
+mod net_watch;
+mod openapi;
+mod pac;
+mod profiles;
+mod quota;
+mod route_test;
+mod rule_sets;
 mod settings;
-
+mod singbox_config;
+mod split_lists;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod traffic;
+mod traffic_usage;
+mod tunnel;
+
+use crate::browser_api::BrowserApiStatus;
+use crate::browser_pairing::PairingRequestView;
+use crate::browser_pairing::PairingRequests;
+use crate::browser_pairing::PairingView;
+use crate::events::AppEvent;
+use crate::events::RoutingSnapshot;
+use crate::events::StatusSnapshot;
+use crate::quota::QuotaStatus;
+use crate::quota::QuotaTracker;
 use crate::settings::LocalSettings;
+use crate::settings::ProfileRoutingOverride;
+use crate::settings::QuotaSettings;
+use crate::settings::RouteAction;
+use crate::settings::RouteRuleKind;
 use crate::settings::SplitRoutingSettings;
-use api::fetch_raw_configs;
-use api::normalize_configs;
+use crate::traffic::TrafficTick;
+use crate::traffic_usage::AppTrafficReport;
+use crate::traffic_usage::TrafficUsage;
+use crate::traffic_usage::TrafficUsageReport;
 use api::ProxyConfig;
-use futures_util::StreamExt;
 #[cfg(target_os = "macos")]
 use libc;
 use serde::Deserialize;
 use serde::Serialize;
+#[cfg(target_os = "macos")]
 use serde_json::json;
-use serde_json::Map;
 use serde_json::Value;
 use std::fs;
 use std::fs::OpenOptions;
@@ -32,6 +73,7 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::Duration;
+#[cfg(windows)]
 use std::time::Instant;
 use sysinfo::ProcessesToUpdate;
 use sysinfo::System;
@@ -47,8 +89,6 @@ use tauri::WindowEvent;
 use tauri_plugin_autostart::MacosLauncher;
 use tauri_plugin_autostart::ManagerExt;
 use tauri_plugin_opener::OpenerExt;
-use tauri_plugin_shell::process::CommandChild;
-use tauri_plugin_shell::ShellExt;
 use tauri_plugin_single_instance::init as single_instance_init;
 use tracing::error;
 use tracing::info;
@@ -66,15 +106,29 @@ const CREATE_NO_WINDOW: u32 = 0x08000000;
 static EXITING: AtomicBool = AtomicBool::new(false);
 static PROFILE_CHECKING: AtomicBool = AtomicBool::new(false);
 
+/// Сколько автоподключений подряд может не дойти до стабильной работы,
+/// прежде чем автоподключение при запуске будет пропускаться (защита от crash loop)
+const AUTO_CONNECT_MAX_FAILURES: u32 = 3;
+const AUTO_CONNECT_STABLE_AFTER: Duration = Duration::from_secs(60);
+
 
 pub struct AppState {
+    pub data_dir: PathBuf,
     pub settings_path: PathBuf,
     pub configs_path: PathBuf,
     pub settings: Mutex<LocalSettings>,
     pub configs: Mutex<Vec<ProxyConfig>>,
     pub running: AtomicBool,
-    pub singbox: Mutex<Option<CommandChild>>,
+    pub singbox: Mutex<Option<std::process::Child>>,
     pub log_guard: Mutex<Option<WorkerGuard>>,
+    pub traffic: Mutex<TrafficTick>,
+    pub usage: Mutex<TrafficUsage>,
+    pub quota: Mutex<QuotaTracker>,
+    pub events: tokio::sync::broadcast::Sender<AppEvent>,
+    pub pairing_requests: Mutex<PairingRequests>,
+    pub routing_reload: tokio::sync::Mutex<()>,
+    pub browser_api_status: Mutex<BrowserApiStatus>,
+    pub browser_api_restart: tokio::sync::Notify,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -132,13 +186,61 @@ fn get_selected_profile(state: SharedState) -> Option<String> {
 
 #[tauri::command]
 fn set_selected_profile(state: State<'_, Arc<AppState>>, profile: String) -> Result<(), String> {
-    let mut s = state.settings.lock().unwrap();
-    s.selected_config = if profile.is_empty() {
-        None
-    } else {
-        Some(profile)
-    };
-    s.save(&state.settings_path)
+    select_profile(state.inner(), profile)
+}
+
+/// Выбор профиля (команды UI и control socket); пустое имя сбрасывает выбор
+fn select_profile(state: &Arc<AppState>, profile: String) -> Result<(), String> {
+    if !profile.is_empty() && profiles::find_config(&state.configs.lock().unwrap(), &profile).is_none() {
+        return Err(format!("Профиль не найден: {}", profile));
+    }
+
+    {
+        let mut s = state.settings.lock().unwrap();
+        s.selected_config = if profile.is_empty() {
+            None
+        } else {
+            Some(profile)
+        };
+        s.save(&state.settings_path)?;
+    }
+    publish_state(state);
+    // у другого профиля могут быть свои переопределения маршрутизации
+    publish_routing(state);
+    Ok(())
+}
+
+fn status_snapshot(state: &Arc<AppState>) -> StatusSnapshot {
+    let s = state.settings.lock().unwrap();
+    StatusSnapshot {
+        running: is_platform_running(state),
+        profile: s.selected_config.clone(),
+        connected_intent: s.connected_intent,
+    }
+}
+
+/// Рассылка текущего состояния подписчикам (control socket, Browser API)
+fn publish_state(state: &Arc<AppState>) {
+    // ошибка — просто нет подписчиков
+    let _ = state.events.send(AppEvent::State(status_snapshot(state)));
+}
+
+/// Снимок с переопределениями выбранного профиля — то, с чем фактически работает ядро
+fn routing_snapshot(state: &Arc<AppState>) -> RoutingSnapshot {
+    let s = state.settings.lock().unwrap();
+    let split = s.active_split_routing();
+    RoutingSnapshot {
+        enabled: split.enabled,
+        tunnel_all: !split.enabled,
+        socks5_enabled: s.socks5_inbound,
+        proxy_domains: split.proxy_domains,
+        bypass_domains: split.bypass_domains,
+        profile_override: s.active_routing_override().is_some(),
+    }
+}
+
+fn publish_routing(state: &Arc<AppState>) {
+    let _ = state.events.send(AppEvent::Routing(routing_snapshot(state)));
 }
 
 #[tauri::command]
@@ -155,6 +257,11 @@ fn get_state(state: SharedState) -> bool {
 
 #[tauri::command]
 fn get_profiles(state: State<'_, Arc<AppState>>) -> Vec<String> {
+    profile_names(state.inner())
+}
+
+/// Имена загруженных профилей (UI, control socket, Browser API)
+fn profile_names(state: &Arc<AppState>) -> Vec<String> {
     state
         .configs
         .lock()
@@ -194,145 +301,6 @@ fn install_privileged_helper() -> Result<(), String> {
     }
 }
 
-fn normalize_primary_outbound_tag(cfg: &mut Value) {
-    let root = match cfg.as_object_mut() {
-        Some(v) => v,
-        None => return,
-    };
-
-    let outbounds = match root.get_mut("outbounds").and_then(|v| v.as_array_mut()) {
-        Some(v) => v,
-        None => return,
-    };
-
-    if outbounds.is_empty() {
-        return;
-    }
-
-    let first = match outbounds.get_mut(0).and_then(|v| v.as_object_mut()) {
-        Some(v) => v,
-        None => return,
-    };
-
-    let current_tag = first.get("tag").and_then(|v| v.as_str()).unwrap_or("");
-
-    if current_tag != "proxy" {
-        first.insert("tag".to_string(), Value::String("proxy".to_string()));
-    }
-
-    let outbound_type = first.get("type").and_then(|v| v.as_str()).unwrap_or("");
-    if outbound_type == "wireguard" {
-        if first.get("server_port").is_none() {
-            first.insert("server_port".to_string(), Value::Number(51820.into()));
-        }
-    }
-}
-
-fn ensure_clash_api(cfg: &mut Value) {
-    let root = match cfg.as_object_mut() {
-        Some(v) => v,
-        None => return,
-    };
-
-    let experimental = root
-        .entry("experimental".to_string())
-        .or_insert_with(|| json!({}));
-
-    let experimental_obj = match experimental.as_object_mut() {
-        Some(v) => v,
-        None => return,
-    };
-
-    experimental_obj.insert(
-        "clash_api".to_string(),
-        json!({
-            "external_controller": "127.0.0.1:9090",
-            "secret": "ultunnel-local-secret"
-        }),
-    );
-}
-
-fn write_singbox_config(
-    app: &AppHandle,
-    cfg: &Value,
-    settings: &LocalSettings,
-) -> Result<PathBuf, String> {
-    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
-
-    let path: PathBuf = dir.join("singbox.json");
-
-    let mut v = cfg.clone();
-
-    normalize_primary_outbound_tag(&mut v);
-
-    #[cfg(target_os = "macos")]
-    {
-        patch_config_for_macos(&mut v);
-        patch_config_for_macos_process_rules(&mut v, settings);
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        patch_config_for_windows(&mut v, &settings.split_routing);
-    }
-
-    apply_split_routing(&mut v, &settings.split_routing);
-    apply_socks5_inbound(
-        &mut v,
-        settings.socks5_inbound,
-        &settings.split_routing.proxy_outbound,
-    );
-    ensure_clash_api(&mut v);
-
-    let json = serde_json::to_string_pretty(&v).map_err(|e| e.to_string())?;
-    fs::write(&path, json).map_err(|e| e.to_string())?;
-
-    Ok(path)
-}
-
-#[cfg(target_os = "windows")]
-fn patch_config_for_windows(cfg: &mut serde_json::Value, split: &SplitRoutingSettings) {
-    /*    if !split.enabled {
-        return;
-    }*/
-
-    // 1) Для корректного определения процесса на Windows нужен stack=system
-    if let Some(inbounds) = cfg.get_mut("inbounds").and_then(|v| v.as_array_mut()) {
-        for ib in inbounds.iter_mut() {
-            if ib.get("type").and_then(|v| v.as_str()) == Some("tun") {
-                if let Some(obj) = ib.as_object_mut() {
-                    obj.insert(
-                        "stack".to_string(),
-                        serde_json::Value::String("system".to_string()),
-                    );
-                }
-            }
-        }
-    }
-
-    // 2) Избежать лупов
-    if let Some(root) = cfg.as_object_mut() {
-        let route = root.entry("route").or_insert_with(|| serde_json::json!({}));
-        if let Some(route_obj) = route.as_object_mut() {
-            route_obj
-                .entry("auto_detect_interface".to_string())
-                .or_insert(serde_json::Value::Bool(true));
-        }
-    }
-
-    if split.enabled {
-        if let Some(root) = cfg.as_object_mut() {
-            let route = root.entry("route").or_insert_with(|| serde_json::json!({}));
-            if let Some(route_obj) = route.as_object_mut() {
-                route_obj
-                    .entry("find_process".to_string())
-                    .or_insert(serde_json::Value::Bool(true));
-            }
-        }
-    }
-}
-
 // #[tauri::command]
 pub async fn singbox_start_root(
     config_path: String,
@@ -446,14 +414,9 @@ fn singbox_stop_admin(app: AppHandle) -> Result<(), String> {
     }
 }
 
-#[cfg(target_os = "linux")]
-fn is_root() -> bool {
-    unsafe { libc::geteuid() == 0 }
-}
-
 #[cfg(target_os = "linux")]
 fn relaunch_as_root_if_needed() -> Result<(), String> {
-    if is_root() {
+    if tunnel::is_root() {
         return Ok(());
     }
 
@@ -500,9 +463,9 @@ fn relaunch_as_root_if_needed() -> Result<(), String> {
 
 #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
 async fn singbox_start(
-    app: AppHandle,
-    state: SharedState<'_>,
-    cfg_path: String,
+    state: &Arc<AppState>,
+    cfg: &ProxyConfig,
+    settings: &LocalSettings,
 ) -> Result<(), String> {
     {
         let guard = state.singbox.lock().unwrap();
@@ -511,32 +474,18 @@ async fn singbox_start(
         }
     }
 
-    let sidecar = app
-        .shell()
-        .sidecar("sing-box")
-        .map_err(|e| format!("Не удалось найти sidecar sing-box: {e}"))?;
-
-    let (_rx, child) = sidecar
-        .args(["run", "-c", cfg_path.as_str()])
-        .spawn()
-        .map_err(|e| format!("Не удалось запустить sing-box: {e}"))?;
-
+    let child = tunnel::start(&state.data_dir, cfg, settings).await?;
     *state.singbox.lock().unwrap() = Some(child);
 
-    if !wait_for_clash_api(5000).await {
-        kill_singbox(state.inner());
-        return Err("sing-box запустился, но Clash API на 127.0.0.1:9090 не ответил".into());
-    }
-
     Ok(())
 }
 
 #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
 async fn singbox_stop(
     _app: AppHandle,
-    state: SharedState<'_>,
+    state: &Arc<AppState>,
 ) -> Result<(), String> {
-    kill_singbox(state.inner());
+    kill_singbox(state).await;
     Ok(())
 }
 
@@ -546,47 +495,34 @@ async fn load_configs(state: State<'_, Arc<AppState>>) -> Result<Vec<String>, St
         let s = state.settings.lock().unwrap();
         s.access_key.clone()
     };
-    if access_key.is_empty() {
-        warn!("Попытка загрузить конфиги без access_key");
-        return Err("accessKey не задан".into());
-    }
-
-    info!("Начата загрузка конфигов");
-
-    let raw = match fetch_raw_configs(&access_key).await {
-        Ok(v) => {
-            info!("Сырые конфиги успешно загружены");
-            v
-        }
-        Err(e) => {
-            error!("Ошибка загрузки конфигов: {}", e);
-            return Err(e);
-        }
-    };
 
-    let configs = match normalize_configs(raw) {
-        Ok(v) => {
-            info!("Конфиги успешно нормализованы, count={}", v.len());
-            v
-        }
-        Err(e) => {
-            error!("Ошибка нормализации конфигов: {}", e);
-            return Err(e);
-        }
-    };
+    let configs = profiles::fetch_configs(&access_key).await?;
 
     {
         let mut stored = state.configs.lock().unwrap();
         *stored = configs.clone();
     }
 
-    save_configs_to_file(&state.configs_path, &configs).map_err(|e| {
+    profiles::save_configs_to_file(&state.configs_path, &configs).map_err(|e| {
         error!("Ошибка сохранения configs.json: {}", e);
         e
     })?;
 
-    let names = configs.into_iter().map(|c| c.name).collect();
+    let names: Vec<String> = configs.into_iter().map(|c| c.name).collect();
     info!("Список конфигов обновлён");
+
+    let removed = {
+        let mut s = state.settings.lock().unwrap();
+        let removed = s.retain_profile_routing(&names);
+        if removed {
+            s.save(&state.settings_path)?;
+        }
+        removed
+    };
+    if removed {
+        info!("Удалены переопределения маршрутизации исчезнувших профилей");
+        publish_routing(state.inner());
+    }
     Ok(names)
 }
 
@@ -653,17 +589,19 @@ pub fn run() {
                 })
                 .build(app)?;
             let handle = app.handle();
-            let settings_path = handle
+            let data_dir = handle
                 .path()
                 .app_data_dir()
-                .expect("cannot get app data dir")
-                .join("config.json");
+                .expect("cannot get app data dir");
+            let settings_path = tunnel::settings_path(&data_dir);
 
             let settings = LocalSettings::load(&settings_path);
-            let configs_path = configs_path_from_settings(&settings_path);
-            let configs = load_configs_from_file(&configs_path);
+            let configs_path = profiles::configs_path_from_settings(&settings_path);
+            let configs = profiles::load_configs_from_file(&configs_path);
+            let usage = TrafficUsage::load(&usage_path_from_settings(&settings_path));
 
             let state = Arc::new(AppState {
+                data_dir,
                 settings_path,
                 configs_path,
                 settings: Mutex::new(settings),
@@ -671,6 +609,14 @@ pub fn run() {
                 running: AtomicBool::new(false),
                 singbox: Mutex::new(None),
                 log_guard: Mutex::new(None),
+                traffic: Mutex::new(TrafficTick::default()),
+                usage: Mutex::new(usage),
+                quota: Mutex::new(QuotaTracker::default()),
+                events: events::channel(),
+                pairing_requests: Mutex::new(PairingRequests::default()),
+                routing_reload: tokio::sync::Mutex::new(()),
+                browser_api_status: Mutex::new(BrowserApiStatus::default()),
+                browser_api_restart: tokio::sync::Notify::new(),
             });
 
             if let Ok(guard) = init_file_logger(&handle) {
@@ -680,8 +626,26 @@ pub fn run() {
                 eprintln!("Не удалось инициализировать app.log");
             }
 
+            // правила могли остаться от предыдущего запуска, если намерение уже сброшено
+            #[cfg(target_os = "linux")]
+            {
+                let s = state.settings.lock().unwrap();
+                if s.kill_switch_enabled && !s.connected_intent {
+                    if let Err(e) = kill_switch::disable() {
+                        warn!("Не удалось снять правила kill switch: {}", e);
+                    }
+                }
+            }
+
             app.manage(state.clone());
-            browser_api::spawn_browser_api(state.clone());
+            browser_api::spawn_browser_api(handle.clone(), state.clone());
+            #[cfg(unix)]
+            control_socket::spawn_control_socket(handle.clone(), state.clone());
+            traffic::spawn_traffic_monitor(handle.clone(), state.clone());
+            rule_sets::spawn_rule_set_updater(handle.clone(), state.clone());
+            spawn_auto_connect(handle.clone(), state.clone());
+            #[cfg(target_os = "linux")]
+            net_watch::spawn_network_watch(handle.clone(), state.clone());
             Ok(())
         })
         .on_window_event(|window, event| {
@@ -706,6 +670,11 @@ pub fn run() {
             open_logs,
             get_split_routing,
             set_split_routing,
+            test_route,
+            import_split_lists,
+            export_split_lists,
+            get_profile_routing,
+            set_profile_routing,
             list_running_apps,
             get_socks5_inbound,
             set_socks5_inbound,
@@ -717,8 +686,34 @@ pub fn run() {
             set_macos_process_tunnel_enabled,
             get_autostart_status,
             set_autostart_enabled,
+            get_launch_settings,
+            set_launch_settings,
+            get_reconnect_on_network_change,
+            set_reconnect_on_network_change,
             get_dashboard_stats,
             check_profiles,
+            get_traffic_usage,
+            reset_traffic_usage,
+            get_quotas,
+            set_quotas,
+            get_quota_status,
+            get_app_traffic,
+            get_kill_switch_enabled,
+            set_kill_switch_enabled,
+            get_app_traffic_tracking,
+            set_app_traffic_tracking,
+            get_browser_pairings,
+            get_pending_browser_pairings,
+            confirm_browser_pairing,
+            revoke_browser_pairing,
+            get_browser_api_status,
+            set_browser_api_port,
+            #[cfg(target_os = "linux")]
+            get_systemd_unit,
+            #[cfg(target_os = "linux")]
+            install_systemd_service,
+            #[cfg(target_os = "linux")]
+            uninstall_systemd_service,
         ])
         .build(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -730,41 +725,36 @@ pub fn run() {
             api.prevent_exit();
             let app = app_handle.clone();
             tauri::async_runtime::spawn(async move {
-                stop_singbox_before_exit(&app);
+                stop_singbox_before_exit(&app).await;
+                if let Some(state) = app.try_state::<Arc<AppState>>() {
+                    if let Err(e) = state.usage.lock().unwrap().end_session() {
+                        error!("Ошибка сохранения traffic-usage.json: {}", e);
+                    }
+                }
                 app.exit(0);
             });
         }
     });
 }
 
-fn configs_path_from_settings(settings_path: &Path) -> PathBuf {
-    settings_path.with_file_name("configs.json")
-}
-
-fn load_configs_from_file(path: &Path) -> Vec<ProxyConfig> {
-    if let Ok(s) = fs::read_to_string(path) {
-        serde_json::from_str::<Vec<ProxyConfig>>(&s).unwrap_or_default()
-    } else {
-        Vec::new()
-    }
+fn usage_path_from_settings(settings_path: &Path) -> PathBuf {
+    settings_path.with_file_name("traffic-usage.json")
 }
 
-fn save_configs_to_file(path: &Path, configs: &[ProxyConfig]) -> Result<(), String> {
-    if let Some(dir) = path.parent() {
-        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+async fn kill_singbox(state: &Arc<AppState>) {
+    // по pid-файлу: ядро могло быть запущено и из CLI
+    if let Err(e) = tunnel::stop_singbox_async(&state.data_dir).await {
+        warn!("{}", e);
     }
-    let json = serde_json::to_string_pretty(configs).map_err(|e| e.to_string())?;
-    fs::write(path, json).map_err(|e| e.to_string())
-}
-
-fn kill_singbox(state: &Arc<AppState>) {
-    if let Some(child) = state.singbox.lock().unwrap().take() {
+    if let Some(mut child) = state.singbox.lock().unwrap().take() {
         let _ = child.kill();
+        let _ = child.wait();
     }
     state.running.store(false, Ordering::Relaxed);
 }
 
-fn stop_singbox_before_exit(app: &tauri::AppHandle) {
+/// Вызывается из задачи async-рантайма, поэтому block_on здесь нельзя
+async fn stop_singbox_before_exit(app: &tauri::AppHandle) {
     // Всегда помечаем как "не запущено" в состоянии
     if let Some(state) = app.try_state::<Arc<AppState>>() {
         state.running.store(false, Ordering::Relaxed);
@@ -772,7 +762,7 @@ fn stop_singbox_before_exit(app: &tauri::AppHandle) {
     // macOS: у вас stop идет через osascript (потребует прав)
     #[cfg(target_os = "macos")]
     {
-        let _ = tauri::async_runtime::block_on(singbox_stop_root(app.clone()));
+        let _ = singbox_stop_root(app.clone()).await;
         return;
     }
     // Windows: у вас stop идет через taskkill, запускаемый runas (может показать UAC)
@@ -781,11 +771,11 @@ fn stop_singbox_before_exit(app: &tauri::AppHandle) {
         let _ = singbox_stop_admin(app.clone());
         return;
     }
-    // Linux/прочие: sing-box у вас живет как CommandChild в state.singbox
+    // Linux/прочие: sing-box запущен из приложения (state.singbox) или из CLI (pid-файл)
     #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
     {
         if let Some(state) = app.try_state::<Arc<AppState>>() {
-            kill_singbox(&state);
+            kill_singbox(&state).await;
         }
     }
 }
@@ -855,21 +845,92 @@ fn singbox_bin_path(app: &AppHandle) -> Result<PathBuf, String> {
     Err(format!("sing-box not found: {:?} / {:?}", p, p2))
 }
 
-#[cfg(target_os = "macos")]
-fn patch_config_for_macos(cfg: &mut Value) {
-    if let Some(inbounds) = cfg.get_mut("inbounds").and_then(|v| v.as_array_mut()) {
-        for ib in inbounds.iter_mut() {
-            if ib.get("type").and_then(|v| v.as_str()) == Some("tun") {
-                if let Some(obj) = ib.as_object_mut() {
-                    obj.remove("interface_name");
-                }
-            }
+#[tauri::command]
+async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Result<(), String> {
+    connect_requested(app, state.inner()).await
+}
+
+/// Подключение по действию пользователя (UI, control socket): сбрасывает счётчик неудачных автоподключений
+async fn connect_requested(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
+    connect(app, state).await?;
+    set_auto_connect_failures(state, 0);
+    Ok(())
+}
+
+#[tauri::command]
+async fn singbox_stop_platform(
+    app: tauri::AppHandle,
+    state: SharedState<'_>,
+) -> Result<(), String> {
+    disconnect(app, state.inner()).await
+}
+
+fn set_connected_intent(state: &Arc<AppState>, connected: bool) -> Result<(), String> {
+    {
+        let mut s = state.settings.lock().unwrap();
+        if s.connected_intent == connected {
+            return Ok(());
         }
+        s.connected_intent = connected;
+        s.save(&state.settings_path)?;
     }
+    publish_state(state);
+    Ok(())
 }
 
-#[tauri::command]
-async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Result<(), String> {
+/// Явное подключение: запоминаем намерение «подключён» (на нём держится kill switch).
+/// Если запуск не удался, намерение сбрасывается, чтобы не оставить систему без сети.
+async fn connect(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
+    set_connected_intent(state, true)?;
+    let r = start_platform(app.clone(), state).await;
+    if r.is_err() {
+        let _ = disconnect(app, state).await;
+    }
+    r
+}
+
+/// Явное отключение: останавливаем ядро, сбрасываем намерение и снимаем kill switch
+async fn disconnect(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
+    let r = stop_platform(app, state).await;
+    set_connected_intent(state, false)?;
+
+    #[cfg(target_os = "linux")]
+    if state.settings.lock().unwrap().kill_switch_enabled {
+        kill_switch::disable()?;
+    }
+
+    r
+}
+
+/// Запуск sing-box с выбранным профилем (общий для команд UI, Browser API и фоновых задач)
+async fn start_platform(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
+    let r = start_core(app, state).await;
+    publish_state(state);
+    r
+}
+
+async fn stop_platform(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
+    let r = stop_core(app, state).await;
+    publish_state(state);
+    r
+}
+
+/// Применение изменённых настроек маршрутизации: если ядро запущено, конфиг генерируется
+/// заново и sing-box перезапускается. `Ok(true)` — изменения уже действуют.
+async fn apply_routing_change(app: AppHandle, state: &Arc<AppState>) -> Result<bool, String> {
+    // перезапуски подряд (несколько переключений из расширения) идут по очереди
+    let _guard = state.routing_reload.lock().await;
+    if !is_platform_running(state) {
+        return Ok(false);
+    }
+
+    info!("Настройки маршрутизации изменились, перезапуск sing-box");
+    let _ = stop_platform(app.clone(), state).await;
+    start_platform(app, state).await?;
+    Ok(true)
+}
+
+async fn start_core(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
     // уже запущено — считаем успехом
     #[cfg(target_os = "windows")]
     {
@@ -891,15 +952,25 @@ async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Resul
     // найти конфиг
     let cfg = {
         let list = state.configs.lock().unwrap();
-        list.iter()
-            .find(|c| c.name == selected)
+        profiles::find_config(&list, &selected)
             .cloned()
             .ok_or("Выбранный конфиг не найден (обновите список)")?
     };
 
     let settings = { state.settings.lock().unwrap().clone() };
-    let cfg_path = write_singbox_config(&app, &cfg.config, &settings)?;
-    let cfg_path_str = cfg_path.to_string_lossy().to_string();
+
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    rule_sets::fetch_missing(&state.data_dir, &settings.split_routing_for(Some(&cfg.name))).await;
+
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    let cfg_path_str = singbox_config::write_singbox_config(
+        &state.data_dir,
+        &cfg.config,
+        &settings,
+        Some(&cfg.name),
+    )?
+        .to_string_lossy()
+        .to_string();
 
     #[cfg(target_os = "macos")]
     {
@@ -909,7 +980,7 @@ async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Resul
             return r;
         }
 
-        if !wait_for_clash_api(5000).await {
+        if !clash_api::wait_ready(5000).await {
             state.running.store(false, Ordering::Relaxed);
             return Err("sing-box запустился, но Clash API на 127.0.0.1:9090 не ответил".into());
         }
@@ -931,7 +1002,7 @@ async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Resul
             return Err("sing-box не запустился (process not found)".into());
         }
 
-        if !wait_for_clash_api(5000).await {
+        if !clash_api::wait_ready(5000).await {
             state.running.store(false, Ordering::Relaxed);
             return Err("sing-box запустился, но Clash API на 127.0.0.1:9090 не ответил".into());
         }
@@ -942,7 +1013,8 @@ async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Resul
 
     #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
     {
-        let r = singbox_start(app, state.clone(), cfg_path_str).await;
+        let _ = app;
+        let r = singbox_start(state, &cfg, &settings).await;
         if r.is_ok() {
             state.running.store(true, Ordering::Relaxed);
         }
@@ -950,11 +1022,7 @@ async fn singbox_start_platform(app: AppHandle, state: SharedState<'_>) -> Resul
     }
 }
 
-#[tauri::command]
-async fn singbox_stop_platform(
-    app: tauri::AppHandle,
-    state: SharedState<'_>,
-) -> Result<(), String> {
+async fn stop_core(app: AppHandle, state: &Arc<AppState>) -> Result<(), String> {
     #[cfg(target_os = "macos")]
     {
         let r = singbox_stop_root(app).await;
@@ -971,7 +1039,7 @@ async fn singbox_stop_platform(
 
     #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
     {
-        let r = singbox_stop(app, state.clone()).await;
+        let r = singbox_stop(app, state).await;
         state.running.store(false, Ordering::Relaxed);
         return r;
     }
@@ -979,167 +1047,217 @@ async fn singbox_stop_platform(
 
 #[tauri::command]
 fn get_split_routing(state: SharedState) -> SplitRoutingSettings {
-    state.settings.lock().unwrap().split_routing.clone()
-}
-
-#[tauri::command]
-fn set_split_routing(state: SharedState, split: SplitRoutingSettings) -> Result<(), String> {
-    let mut s = state.settings.lock().unwrap();
-    s.split_routing = split;
-    s.save(&state.settings_path)
+    let mut split = state.settings.lock().unwrap().split_routing.clone();
+    split.normalize_rule_order();
+    split
 }
 
-fn split_process_tokens(list: &[String]) -> (Vec<String>, Vec<String>) {
-    let mut names: Vec<String> = Vec::new();
-    let mut paths: Vec<String> = Vec::new();
-
-    for s in list {
-        let t = s.trim();
-        if t.is_empty() {
+/// Проверка собственных правил: те же форматы, что и во встроенных списках
+fn normalize_custom_rules(split: &mut SplitRoutingSettings) -> Result<(), String> {
+    for (i, rule) in split.rules.iter_mut().enumerate() {
+        let RouteRuleKind::Custom(custom) = &mut rule.kind else {
             continue;
-        }
+        };
+        let label = if custom.name.trim().is_empty() {
+            format!("Правило {}", i + 1)
+        } else {
+            format!("Правило «{}»", custom.name.trim())
+        };
 
-        // Путь
-        if t.contains('/') || t.contains('\\') {
-            paths.push(t.to_string());
+        custom.name = custom.name.trim().to_string();
+        custom.apps = custom
+            .apps
+            .iter()
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty())
+            .collect();
+        custom.domains = domains::normalize_domain_rules(&custom.domains)
+            .map_err(|e| format!("{}, домены: {}", label, e))?;
+        custom.ips = net_rules::normalize_list(&custom.ips, net_rules::normalize_cidr)
+            .map_err(|e| format!("{}, IP: {}", label, e))?;
+        custom.ports = net_rules::normalize_list(&custom.ports, net_rules::normalize_port_rule)
+            .map_err(|e| format!("{}, порты: {}", label, e))?;
+
+        if custom.apps.is_empty()
+            && custom.domains.is_empty()
+            && custom.ips.is_empty()
+            && custom.ports.is_empty()
+        {
+            return Err(format!("{}: не задано ни одного условия", label));
+        }
 
-            #[cfg(target_os = "windows")]
-            {
-                let low = t.to_ascii_lowercase();
-                if low != t {
-                    paths.push(low);
-                }
+        if custom.action == RouteAction::Outbound {
+            let tag = custom.outbound.as_deref().map(str::trim).unwrap_or("");
+            if tag.is_empty() {
+                return Err(format!("{}: не указан тег outbound", label));
             }
-
-            continue;
+            custom.outbound = Some(tag.to_string());
+        } else {
+            custom.outbound = None;
         }
+    }
+    Ok(())
+}
 
-        // Имя процесса
-        names.push(t.to_string());
-
-        #[cfg(target_os = "windows")]
-        {
-            let low = t.to_ascii_lowercase();
-            if low != t {
-                names.push(low.clone());
-            }
+/// Сохраняет настройки после проверки доменов, подсетей и портов и возвращает их в нормализованном виде
+#[tauri::command]
+fn set_split_routing(
+    state: SharedState,
+    mut split: SplitRoutingSettings,
+) -> Result<SplitRoutingSettings, String> {
+    split.proxy_domains = domains::normalize_domain_rules(&split.proxy_domains)
+        .map_err(|e| format!("Домены через прокси: {}", e))?;
+    split.bypass_domains = domains::normalize_domain_rules(&split.bypass_domains)
+        .map_err(|e| format!("Домены напрямую: {}", e))?;
+    split.block_domains = domains::normalize_domain_rules(&split.block_domains)
+        .map_err(|e| format!("Блокируемые домены: {}", e))?;
+    split.proxy_ips = net_rules::normalize_list(&split.proxy_ips, net_rules::normalize_cidr)
+        .map_err(|e| format!("IP через прокси: {}", e))?;
+    split.bypass_ips = net_rules::normalize_list(&split.bypass_ips, net_rules::normalize_cidr)
+        .map_err(|e| format!("IP напрямую: {}", e))?;
+    split.block_ips = net_rules::normalize_list(&split.block_ips, net_rules::normalize_cidr)
+        .map_err(|e| format!("Блокируемые IP: {}", e))?;
+    split.proxy_ports = net_rules::normalize_list(&split.proxy_ports, net_rules::normalize_port_rule)
+        .map_err(|e| format!("Порты через прокси: {}", e))?;
+    split.bypass_ports = net_rules::normalize_list(&split.bypass_ports, net_rules::normalize_port_rule)
+        .map_err(|e| format!("Порты напрямую: {}", e))?;
+    rule_sets::validate(&split).map_err(|e| format!("Rule-set'ы: {}", e))?;
+    split.normalize_rule_order();
+    normalize_custom_rules(&mut split)?;
 
-            // добавить вариант с .exe и без .exe
-            if low.ends_with(".exe") {
-                let no = low.trim_end_matches(".exe").to_string();
-                if !no.is_empty() {
-                    names.push(no);
-                }
-            } else if !low.contains('.') {
-                names.push(format!("{low}.exe"));
-            }
-        }
+    {
+        let mut s = state.settings.lock().unwrap();
+        s.split_routing = split.clone();
+        s.save(&state.settings_path)?;
     }
+    publish_routing(&state);
+    Ok(split)
+}
 
-    (names, paths)
+#[tauri::command]
+fn get_profile_routing(state: SharedState, profile: String) -> ProfileRoutingOverride {
+    state
+        .settings
+        .lock()
+        .unwrap()
+        .profile_routing
+        .get(&profile)
+        .cloned()
+        .unwrap_or_default()
 }
 
-fn apply_split_routing(cfg: &mut serde_json::Value, split: &SplitRoutingSettings) {
-    if !split.enabled {
-        return;
+/// Переопределения маршрутизации профиля; пустые удаляются. Если профиль сейчас
+/// подключён, ядро перезапускается с новым конфигом
+#[tauri::command]
+async fn set_profile_routing(
+    app: AppHandle,
+    state: SharedState<'_>,
+    profile: String,
+    mut routing: ProfileRoutingOverride,
+) -> Result<ProfileRoutingOverride, String> {
+    if profiles::find_config(&state.configs.lock().unwrap(), &profile).is_none() {
+        return Err(format!("Профиль не найден: {}", profile));
+    }
+    for list in [&mut routing.proxy_apps, &mut routing.bypass_apps] {
+        *list = list
+            .iter()
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty())
+            .collect();
     }
-
-    let root = match cfg.as_object_mut() {
-        Some(v) => v,
-        None => return,
+    routing.proxy_domains = domains::normalize_domain_rules(&routing.proxy_domains)
+        .map_err(|e| format!("Домены через прокси: {}", e))?;
+    routing.bypass_domains = domains::normalize_domain_rules(&routing.bypass_domains)
+        .map_err(|e| format!("Домены напрямую: {}", e))?;
+    routing.proxy_ips = net_rules::normalize_list(&routing.proxy_ips, net_rules::normalize_cidr)
+        .map_err(|e| format!("IP через прокси: {}", e))?;
+    routing.bypass_ips = net_rules::normalize_list(&routing.bypass_ips, net_rules::normalize_cidr)
+        .map_err(|e| format!("IP напрямую: {}", e))?;
+    routing.final_outbound = routing
+        .final_outbound
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string);
+
+    let selected = {
+        let mut s = state.settings.lock().unwrap();
+        if routing == ProfileRoutingOverride::default() {
+            s.profile_routing.remove(&profile);
+        } else {
+            s.profile_routing.insert(profile.clone(), routing.clone());
+        }
+        s.save(&state.settings_path)?;
+        s.selected_config.as_deref() == Some(profile.as_str())
     };
 
-    if !root.contains_key("route") || !root.get("route").unwrap().is_object() {
-        root.insert("route".into(), Value::Object(Map::new()));
+    if selected {
+        publish_routing(&state);
+        apply_routing_change(app, state.inner()).await?;
     }
+    Ok(routing)
+}
 
-    let route = root
-        .get_mut("route")
-        .and_then(|v| v.as_object_mut())
-        .unwrap();
-
-    route.insert(
-        "final".to_string(),
-        Value::String(split.direct_outbound.clone()),
-    );
-
-    route.insert("auto_detect_interface".to_string(), Value::Bool(true));
-
-    let has_process_rules = split.bypass_apps.iter().any(|s| !s.trim().is_empty())
-        || split.proxy_apps.iter().any(|s| !s.trim().is_empty());
+/// Импорт списков доменов и приложений; результат сохраняется сразу, отчёт — для UI.
+/// Если ядро запущено, оно перезапускается с новыми правилами. Списки к этому моменту
+/// уже сохранены, поэтому ошибка перезапуска возвращается в отчёте (apply_error)
+#[tauri::command]
+async fn import_split_lists(
+    app: AppHandle,
+    state: SharedState<'_>,
+    content: String,
+    format: split_lists::ListFormat,
+    mode: split_lists::ImportMode,
+    list: Option<split_lists::ListName>,
+) -> Result<split_lists::ImportReport, String> {
+    let mut report = {
+        let mut s = state.settings.lock().unwrap();
+        let mut split = s.split_routing.clone();
+        let report = split_lists::import(&mut split, &content, format, mode, list)?;
+        s.split_routing = split;
+        s.save(&state.settings_path)?;
+        report
+    };
+    publish_routing(&state);
 
-    if has_process_rules {
-        route.insert("find_process".to_string(), Value::Bool(true));
+    if let Err(e) = apply_routing_change(app, state.inner()).await {
+        error!("Не удалось применить импортированные списки: {}", e);
+        report.apply_error = Some(e);
     }
+    Ok(report)
+}
 
-    let mut rules: Vec<Value> = vec![
-        json!({ "inbound": ["tun-in"], "action": "sniff" }),
-        json!({ "protocol": ["dns"], "action": "hijack-dns" }),
-    ];
+#[tauri::command]
+fn export_split_lists(
+    state: SharedState,
+    format: split_lists::ListFormat,
+    list: Option<split_lists::ListName>,
+) -> Result<String, String> {
+    let split = state.settings.lock().unwrap().split_routing.clone();
+    split_lists::export(&split, format, list)
+}
 
-    let (bypass_names, bypass_paths) = split_process_tokens(&split.bypass_apps);
-    if !bypass_paths.is_empty() {
-        rules.push(json!({
-            "inbound": ["tun-in"],
-            "process_path": bypass_paths,
-            "outbound": split.direct_outbound
-        }));
-    }
-    if !bypass_names.is_empty() {
-        rules.push(json!({
-            "inbound": ["tun-in"],
-            "process_name": bypass_names,
-            "outbound": split.direct_outbound
-        }));
-    }
+/// Какое правило сгенерированного конфига сработает для назначения. Берётся выбранный
+/// профиль, без него — конфиг только из правил раздельной маршрутизации
+#[tauri::command]
+fn test_route(
+    state: SharedState,
+    query: route_test::RouteQuery,
+) -> Result<route_test::RouteMatch, String> {
+    let settings = state.settings.lock().unwrap().clone();
+    let profile = settings.selected_config.as_ref().and_then(|name| {
+        let list = state.configs.lock().unwrap();
+        profiles::find_config(&list, name).map(|c| c.config.clone())
+    });
 
-    let bypass_domains: Vec<String> = split
-        .bypass_domains
-        .iter()
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    if !bypass_domains.is_empty() {
-        rules.push(json!({
-            "inbound": ["tun-in"],
-            "domain_suffix": bypass_domains,
-            "outbound": split.direct_outbound
-        }));
-    }
-
-    let (proxy_names, proxy_paths) = split_process_tokens(&split.proxy_apps);
-    if !proxy_paths.is_empty() {
-        rules.push(json!({
-            "inbound": ["tun-in"],
-            "process_path": proxy_paths,
-            "outbound": split.proxy_outbound
-        }));
-    }
-    if !proxy_names.is_empty() {
-        rules.push(json!({
-            "inbound": ["tun-in"],
-            "process_name": proxy_names,
-            "outbound": split.proxy_outbound
-        }));
-    }
-
-    let proxy_domains: Vec<String> = split
-        .proxy_domains
-        .iter()
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
-    if !proxy_domains.is_empty() {
-        rules.push(json!({
-            "inbound": ["tun-in"],
-            "domain_suffix": proxy_domains,
-            "outbound": split.proxy_outbound
-        }));
-    }
-
-    route.insert("rules".to_string(), Value::Array(rules));
-}
+    let cfg = singbox_config::build_singbox_config(
+        &state.data_dir,
+        &profile.unwrap_or_else(|| serde_json::json!({})),
+        &settings,
+        settings.selected_config.as_deref(),
+    );
+    route_test::evaluate(&cfg, &query)
+}
 
 #[tauri::command]
 fn list_running_apps() -> Result<Vec<RunningApp>, String> {
@@ -1290,232 +1408,13 @@ fn get_socks5_inbound(state: SharedState) -> bool {
 
 #[tauri::command]
 fn set_socks5_inbound(state: SharedState, enabled: bool) -> Result<(), String> {
-    let mut s = state.settings.lock().unwrap();
-    s.socks5_inbound = enabled;
-    s.save(&state.settings_path)
-}
-
-fn apply_socks5_inbound(cfg: &mut serde_json::Value, enabled: bool, proxy_outbound: &str) {
-    let root = match cfg.as_object_mut() {
-        Some(v) => v,
-        None => return,
-    };
-
-    // --- inbounds ---
-    if !root.contains_key("inbounds") || !root.get("inbounds").unwrap().is_array() {
-        root.insert("inbounds".into(), Value::Array(vec![]));
-    }
-    let inbounds = root
-        .get_mut("inbounds")
-        .and_then(|v| v.as_array_mut())
-        .unwrap();
-
-    // удалить старый socks-in (если был)
-    inbounds.retain(|ib| ib.get("tag").and_then(|v| v.as_str()) != Some("socks-in"));
-
-    if enabled {
-        inbounds.push(json!({
-            "type": "socks",
-            "tag": "socks-in",
-            "listen": "127.0.0.1",
-            "listen_port": 5613,
-        }));
-    }
-
-    // --- route.rules ---
-    if !root.contains_key("route") || !root.get("route").unwrap().is_object() {
-        root.insert("route".into(), Value::Object(Map::new()));
-    }
-    let route = root
-        .get_mut("route")
-        .and_then(|v| v.as_object_mut())
-        .unwrap();
-
-    if !route.contains_key("rules") || !route.get("rules").unwrap().is_array() {
-        route.insert("rules".into(), Value::Array(vec![]));
-    }
-    let rules = route
-        .get_mut("rules")
-        .and_then(|v| v.as_array_mut())
-        .unwrap();
-
-    // удалить старые правила, относящиеся к socks-in:
-    // 1) sniff action для socks-in
-    // 2) простое правило socks-in -> outbound
-    rules.retain(|r| {
-        let o = match r.as_object() {
-            Some(x) => x,
-            None => return true,
-        };
-
-        let inbound_is_socks = match o.get("inbound").and_then(|v| v.as_array()) {
-            Some(a) => a.iter().any(|x| x.as_str() == Some("socks-in")),
-            None => false,
-        };
-
-        if !inbound_is_socks {
-            return true;
-        }
-
-        let action = o.get("action").and_then(|v| v.as_str());
-        if action == Some("sniff") {
-            return false;
-        }
-
-        let outbound = o.get("outbound").and_then(|v| v.as_str());
-        let has_action = o.contains_key("action");
-        let has_other_conditions = o.contains_key("process_name")
-            || o.contains_key("process_path")
-            || o.contains_key("domain_suffix")
-            || o.contains_key("ip_cidr")
-            || o.contains_key("port")
-            || o.contains_key("network")
-            || o.contains_key("protocol");
-
-        if !has_action && !has_other_conditions && outbound.is_some() {
-            return false;
-        }
-
-        true
-    });
-
-    if enabled {
-        // вставить sniff и outbound-правило как можно выше
-        // сразу после уже существующих sniff/hijack-dns правил
-        let mut insert_at = 0usize;
-        for (i, r) in rules.iter().enumerate() {
-            let action = r
-                .as_object()
-                .and_then(|o| o.get("action"))
-                .and_then(|a| a.as_str());
-            if action == Some("sniff") || action == Some("hijack-dns") {
-                insert_at = i + 1;
-            }
-        }
-
-        rules.insert(
-            insert_at,
-            json!({
-                "inbound": ["socks-in"],
-                "action": "sniff"
-            }),
-        );
-        insert_at += 1;
-
-        let out = if proxy_outbound.trim().is_empty() {
-            "proxy"
-        } else {
-            proxy_outbound
-        };
-
-        rules.insert(
-            insert_at,
-            json!({
-                "inbound": ["socks-in"],
-                "outbound": out
-            }),
-        );
-    }
-}
-
-fn collect_tun_inbound_tags_for_profile_check(cfg: &serde_json::Value) -> Vec<String> {
-    cfg.get("inbounds")
-        .and_then(|v| v.as_array())
-        .map(|inbounds| {
-            inbounds
-                .iter()
-                .filter(|ib| ib.get("type").and_then(|v| v.as_str()) == Some("tun"))
-                .filter_map(|ib| ib.get("tag").and_then(|v| v.as_str()))
-                .map(|s| s.to_string())
-                .collect::<Vec<_>>()
-        })
-        .unwrap_or_default()
-}
-
-fn configure_full_vpn_profile_check(cfg: &mut serde_json::Value, tun_tags: &[String]) {
-    let root = match cfg.as_object_mut() {
-        Some(v) => v,
-        None => return,
-    };
-
-    // Проверка профилей должна поднимать именно полноценный TUN/VPN,
-    // а не локальный proxy-inbound. Поэтому для временного конфига проверки
-    // отключаем split-routing/process-routing и делаем proxy финальным outbound
-    // для всего системного трафика, попадающего в TUN.
-    if !root.contains_key("route") || !root.get("route").unwrap().is_object() {
-        root.insert("route".into(), Value::Object(Map::new()));
-    }
-
-    let route = root
-        .get_mut("route")
-        .and_then(|v| v.as_object_mut())
-        .unwrap();
-
-    route.insert("final".to_string(), Value::String("proxy".to_string()));
-    route.insert("auto_detect_interface".to_string(), Value::Bool(true));
-    route.remove("find_process");
-
-    // Убираем правила, которые могли увести часть трафика напрямую по process_name,
-    // process_path, domain_suffix и т.п. Для проверки нужен чистый full-tunnel:
-    // DNS перехватывается, остальной трафик идет в route.final = proxy.
-    route.insert(
-        "rules".to_string(),
-        Value::Array(vec![
-            json!({ "inbound": tun_tags, "action": "sniff" }),
-            json!({ "protocol": ["dns"], "action": "hijack-dns" }),
-        ]),
-    );
-}
-
-fn ensure_tun_inbound_exists_for_profile_check(cfg: &serde_json::Value) -> Result<Vec<String>, String> {
-    let tun_tags = collect_tun_inbound_tags_for_profile_check(cfg);
-
-    if !tun_tags.is_empty() {
-        Ok(tun_tags)
-    } else {
-        Err("В конфиге нет TUN inbound, поэтому полноценный VPN для проверки поднять нельзя".to_string())
-    }
-}
-
-fn write_singbox_config_for_profile_check(
-    app: &AppHandle,
-    cfg: &Value,
-    settings: &LocalSettings,
-) -> Result<PathBuf, String> {
-    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
-
-    let path: PathBuf = dir.join("singbox-profile-check.json");
-    let mut v = cfg.clone();
-
-    normalize_primary_outbound_tag(&mut v);
-    let tun_tags = ensure_tun_inbound_exists_for_profile_check(&v)?;
-
-    #[cfg(target_os = "macos")]
     {
-        let _ = settings;
-        patch_config_for_macos(&mut v);
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        let mut full_tunnel_split = settings.split_routing.clone();
-        full_tunnel_split.enabled = false;
-        patch_config_for_windows(&mut v, &full_tunnel_split);
-    }
-
-    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
-    {
-        let _ = settings;
+        let mut s = state.settings.lock().unwrap();
+        s.socks5_inbound = enabled;
+        s.save(&state.settings_path)?;
     }
-
-    configure_full_vpn_profile_check(&mut v, &tun_tags);
-    ensure_clash_api(&mut v);
-
-    let json = serde_json::to_string_pretty(&v).map_err(|e| e.to_string())?;
-    fs::write(&path, json).map_err(|e| e.to_string())?;
-
-    Ok(path)
+    publish_routing(&state);
+    Ok(())
 }
 
 #[tauri::command]
@@ -1578,46 +1477,6 @@ fn set_macos_process_tunnel_enabled(state: SharedState, enabled: bool) -> Result
 }
 /* TODO remove upper */
 
-#[cfg(target_os = "macos")]
-fn patch_config_for_macos_process_rules(cfg: &mut Value, settings: &LocalSettings) {
-    if !settings.macos_process_tunnel_enabled {
-        return;
-    }
-
-    let processes = settings
-        .macos_tunneled_processes
-        .iter()
-        .filter(|s| !s.trim().is_empty())
-        .cloned()
-        .collect::<Vec<_>>();
-    if processes.is_empty() {
-        return;
-    }
-    let route = cfg
-        .as_object_mut()
-        .and_then(|root| root.get_mut("route"))
-        .and_then(|v| v.as_object_mut());
-    if route.is_none() {
-        // если route отсутствует — создаем
-        if let Some(root) = cfg.as_object_mut() {
-            root.insert("route".to_string(), serde_json::json!({}));
-        }
-    }
-    let route = cfg
-        .get_mut("route")
-        .and_then(|v| v.as_object_mut())
-        .unwrap();
-    let rules = route
-        .entry("rules".to_string())
-        .or_insert_with(|| serde_json::json!([]));
-    let rules_arr = rules.as_array_mut().unwrap();
-    let rule = json!({
-        "process_name": processes,
-        "outbound": settings.split_routing.proxy_outbound
-    });
-    rules_arr.insert(0, rule);
-}
-
 #[tauri::command]
 fn get_autostart_status(state: SharedState, app: AppHandle) -> Result<Value, String> {
     let desired = state.settings.lock().unwrap().autostart_enabled;
@@ -1704,6 +1563,120 @@ fn set_autostart_enabled(state: SharedState, _app: AppHandle, enabled: bool) ->
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchSettings {
+    connect_on_launch: bool,
+    restore_previous_state: bool,
+}
+
+#[tauri::command]
+fn get_launch_settings(state: SharedState) -> LaunchSettings {
+    let s = state.settings.lock().unwrap();
+    LaunchSettings {
+        connect_on_launch: s.connect_on_launch,
+        restore_previous_state: s.restore_previous_state,
+    }
+}
+
+#[tauri::command]
+fn set_launch_settings(state: SharedState, launch: LaunchSettings) -> Result<(), String> {
+    let mut s = state.settings.lock().unwrap();
+    s.connect_on_launch = launch.connect_on_launch;
+    s.restore_previous_state = launch.restore_previous_state;
+    s.save(&state.settings_path)
+}
+
+#[tauri::command]
+fn get_reconnect_on_network_change(state: SharedState) -> bool {
+    state.settings.lock().unwrap().reconnect_on_network_change
+}
+
+#[tauri::command]
+fn set_reconnect_on_network_change(state: SharedState, enabled: bool) -> Result<(), String> {
+    let mut s = state.settings.lock().unwrap();
+    s.reconnect_on_network_change = enabled;
+    s.save(&state.settings_path)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AutoConnectEvent {
+    status: String,
+    profile: Option<String>,
+    error: Option<String>,
+}
+
+fn set_auto_connect_failures(state: &Arc<AppState>, failures: u32) {
+    let mut s = state.settings.lock().unwrap();
+    if s.auto_connect_failures == failures {
+        return;
+    }
+    s.auto_connect_failures = failures;
+    if let Err(e) = s.save(&state.settings_path) {
+        error!("Ошибка сохранения config.json: {}", e);
+    }
+}
+
+fn emit_auto_connect_event(app: &AppHandle, status: &str, profile: &Option<String>, error: Option<String>) {
+    let event = AutoConnectEvent {
+        status: status.to_string(),
+        profile: profile.clone(),
+        error,
+    };
+    if let Err(e) = app.emit("auto-connect", event) {
+        warn!("Не удалось отправить событие auto-connect: {}", e);
+    }
+}
+
+/// Автоподключение при запуске: всегда (connect_on_launch) или если приложение
+/// было подключено при прошлом выходе (restore_previous_state + connected_intent).
+/// Счётчик неудач увеличивается до попытки и сбрасывается только после
+/// AUTO_CONNECT_STABLE_AFTER стабильной работы, так что падение во время запуска тоже учитывается.
+fn spawn_auto_connect(app: AppHandle, state: Arc<AppState>) {
+    let (profile, failures) = {
+        let s = state.settings.lock().unwrap();
+        let wanted = s.connect_on_launch || (s.restore_previous_state && s.connected_intent);
+        if !wanted {
+            return;
+        }
+        (s.selected_config.clone(), s.auto_connect_failures)
+    };
+
+    if profile.is_none() {
+        emit_auto_connect_event(&app, "skipped", &profile, Some("Не выбран конфиг".to_string()));
+        return;
+    }
+
+    if failures >= AUTO_CONNECT_MAX_FAILURES {
+        warn!("Автоподключение пропущено: {} неудачных запусков подряд", failures);
+        emit_auto_connect_event(
+            &app,
+            "skipped",
+            &profile,
+            Some(format!("{} неудачных автоподключений подряд", failures)),
+        );
+        return;
+    }
+
+    set_auto_connect_failures(&state, failures + 1);
+    info!("Автоподключение к профилю {:?}", profile);
+
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = connect(app.clone(), &state).await {
+            error!("Автоподключение не удалось: {}", e);
+            emit_auto_connect_event(&app, "failed", &profile, Some(e));
+            return;
+        }
+        emit_auto_connect_event(&app, "connected", &profile, None);
+
+        tokio::time::sleep(AUTO_CONNECT_STABLE_AFTER).await;
+        if is_platform_running(&state) {
+            set_auto_connect_failures(&state, 0);
+        }
+    });
+}
+
 #[cfg(target_os = "macos")]
 fn macos_find_app_bundle() -> Result<PathBuf, String> {
     let mut p = std::env::current_exe().map_err(|e| e.to_string())?;
@@ -1776,58 +1749,6 @@ fn emit_profile_check_event(app: &AppHandle, event: ProfileCheckEvent) {
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct TrafficResponse {
-    up: i64,
-    down: i64,
-}
-
-#[derive(Debug, Deserialize)]
-struct ConnectionsResponse {
-    connections: Option<Vec<serde_json::Value>>,
-}
-
-#[derive(Debug, Deserialize)]
-struct VersionResponse {
-    version: Option<String>,
-}
-
-
-async fn fetch_ipinfo_ip_through_full_vpn() -> Result<String, String> {
-    // Здесь намеренно НЕ используется reqwest::Proxy.
-    // Проверка должна идти как обычный системный трафик приложения после поднятия TUN,
-    // чтобы IP был получен через полноценный VPN-маршрут, а не через локальный порт.
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(20))
-        .no_proxy()
-        .build()
-        .map_err(|e| e.to_string())?;
-
-    let resp = client
-        .get("https://ipinfo.io/ip")
-        .header(reqwest::header::USER_AGENT, "ultunnel-desktop")
-        .send()
-        .await
-        .map_err(|e| format!("ipinfo request through full VPN failed: {}", e))?;
-
-    if !resp.status().is_success() {
-        return Err(format!("ipinfo through full VPN returned HTTP {}", resp.status()));
-    }
-
-    let ip = resp
-        .text()
-        .await
-        .map_err(|e| format!("ipinfo response through full VPN read failed: {}", e))?
-        .trim()
-        .to_string();
-
-    if ip.is_empty() {
-        Err("ipinfo through full VPN returned empty IP".to_string())
-    } else {
-        Ok(ip)
-    }
-}
-
 
 fn is_platform_running(state: &Arc<AppState>) -> bool {
     #[cfg(target_os = "windows")]
@@ -1858,7 +1779,7 @@ async fn stop_platform_for_profile_check(app: AppHandle, state: &Arc<AppState>)
 
     #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
     {
-        kill_singbox(state);
+        kill_singbox(state).await;
         Ok(())
     }
 }
@@ -1869,14 +1790,15 @@ async fn start_profile_for_check(
     cfg: &ProxyConfig,
 ) -> Result<(), String> {
     let settings = { state.settings.lock().unwrap().clone() };
-    let cfg_path = write_singbox_config_for_profile_check(&app, &cfg.config, &settings)?;
+    let cfg_path =
+        singbox_config::write_singbox_config_for_profile_check(&state.data_dir, &cfg.config, &settings)?;
     let cfg_path_str = cfg_path.to_string_lossy().to_string();
 
     #[cfg(target_os = "macos")]
     {
         singbox_start_root(cfg_path_str, None).await?;
 
-        if !wait_for_clash_api(5000).await {
+        if !clash_api::wait_ready(5000).await {
             state.running.store(false, Ordering::Relaxed);
             return Err("sing-box запустился, но Clash API на 127.0.0.1:9090 не ответил".into());
         }
@@ -1894,7 +1816,7 @@ async fn start_profile_for_check(
             return Err("sing-box не запустился (process not found)".into());
         }
 
-        if !wait_for_clash_api(5000).await {
+        if !clash_api::wait_ready(5000).await {
             state.running.store(false, Ordering::Relaxed);
             return Err("sing-box запустился, но Clash API на 127.0.0.1:9090 не ответил".into());
         }
@@ -1957,7 +1879,7 @@ async fn check_profiles(app: AppHandle, state: SharedState<'_>) -> Result<Vec<Pr
         let check = async {
             start_profile_for_check(app.clone(), &state_arc, cfg).await?;
             sleep(Duration::from_millis(2500));
-            fetch_ipinfo_ip_through_full_vpn().await
+            tunnel::fetch_external_ip().await
         }
         .await;
 
@@ -2021,124 +1943,166 @@ async fn check_profiles(app: AppHandle, state: SharedState<'_>) -> Result<Vec<Pr
 }
 
 #[tauri::command]
-async fn get_dashboard_stats(_app: AppHandle) -> Result<DashboardStats, String> {
-    let client = reqwest::Client::new();
-    let secret = "ultunnel-local-secret";
+async fn get_dashboard_stats(state: SharedState<'_>) -> Result<DashboardStats, String> {
+    dashboard_stats(state.inner()).await
+}
 
-    let traffic_resp = client
-        .get("http://127.0.0.1:9090/traffic")
-        .bearer_auth(secret)
-        .send()
-        .await
-        .map_err(|e| format!("traffic request failed: {}", e))?;
+async fn dashboard_stats(state: &Arc<AppState>) -> Result<DashboardStats, String> {
+    // Скорость, соединения и память приходят из фоновой подписки (traffic-tick),
+    // здесь только отдаём последний снимок и версию ядра.
+    let tick = state.traffic.lock().unwrap().clone();
 
-    let mut stream = traffic_resp.bytes_stream();
-    let mut buffer = String::new();
-    let first_line: String;
+    let version = clash_api::version(&reqwest::Client::new()).await?;
 
-    loop {
-        let chunk = stream
-            .next()
-            .await
-            .ok_or("traffic stream ended unexpectedly".to_string())?
-            .map_err(|e| format!("traffic stream read failed: {}", e))?;
+    Ok(DashboardStats {
+        up_bps: tick.up_bps,
+        down_bps: tick.down_bps,
+        active_connections: tick.active_connections,
+        memory_mb: tick.memory_mb,
+        core_version: version,
+    })
+}
 
-        buffer.push_str(&String::from_utf8_lossy(&chunk));
+#[tauri::command]
+fn get_traffic_usage(
+    state: SharedState,
+    days: Option<usize>,
+    months: Option<usize>,
+) -> TrafficUsageReport {
+    state
+        .usage
+        .lock()
+        .unwrap()
+        .report(days.unwrap_or(30), months.unwrap_or(12))
+}
 
-        if let Some(pos) = buffer.find('\n') {
-            first_line = buffer[..pos].trim().to_string();
-            break;
-        }
+#[tauri::command]
+fn reset_traffic_usage(state: SharedState) -> Result<(), String> {
+    state.usage.lock().unwrap().reset()
+}
 
-        let trimmed = buffer.trim();
-        if trimmed.starts_with('{') && trimmed.ends_with('}') {
-            first_line = trimmed.to_string();
-            break;
-        }
+#[tauri::command]
+fn get_app_traffic(state: SharedState, days: Option<usize>) -> AppTrafficReport {
+    state.usage.lock().unwrap().app_report(days.unwrap_or(1))
+}
+
+#[tauri::command]
+fn get_app_traffic_tracking(state: SharedState) -> bool {
+    state.settings.lock().unwrap().app_traffic_tracking
+}
+
+#[tauri::command]
+fn set_app_traffic_tracking(state: SharedState, enabled: bool) -> Result<(), String> {
+    let mut s = state.settings.lock().unwrap();
+    s.app_traffic_tracking = enabled;
+    s.save(&state.settings_path)
+}
+
+#[tauri::command]
+fn get_kill_switch_enabled(state: SharedState) -> bool {
+    state.settings.lock().unwrap().kill_switch_enabled
+}
+
+/// Включение применяется при следующем подключении (нужен перезапуск ядра с именованным TUN),
+/// выключение снимает правила сразу.
+#[tauri::command]
+fn set_kill_switch_enabled(state: SharedState, enabled: bool) -> Result<(), String> {
+    #[cfg(not(target_os = "linux"))]
+    if enabled {
+        return Err("Kill switch поддержан только на Linux".to_string());
     }
 
-    let traffic: TrafficResponse =
-        serde_json::from_str(&first_line).map_err(|e| format!("traffic parse failed: {}", e))?;
+    #[cfg(target_os = "linux")]
+    if !enabled {
+        kill_switch::disable()?;
+    }
 
-    let connections = match client
-        .get("http://127.0.0.1:9090/connections")
-        .bearer_auth(secret)
-        .send()
-        .await
-    {
-        Ok(resp) => resp
-            .json::<ConnectionsResponse>()
-            .await
-            .unwrap_or(ConnectionsResponse { connections: None }),
-        Err(_) => ConnectionsResponse { connections: None },
-    };
+    let mut s = state.settings.lock().unwrap();
+    s.kill_switch_enabled = enabled;
+    s.save(&state.settings_path)
+}
 
-    let version = match client
-        .get("http://127.0.0.1:9090/version")
-        .bearer_auth(secret)
-        .send()
-        .await
-    {
-        Ok(resp) => match resp.json::<VersionResponse>().await {
-            Ok(v) => v.version,
-            Err(_) => None,
-        },
-        Err(_) => None,
-    };
+#[tauri::command]
+fn get_browser_pairings(state: SharedState) -> Vec<PairingView> {
+    browser_pairing::list(&state)
+}
 
-    let memory_mb = current_singbox_memory_mb();
+/// Запросы на сопряжение, пришедшие, пока окно не слушало события
+#[tauri::command]
+fn get_pending_browser_pairings(state: SharedState) -> Vec<PairingRequestView> {
+    state.pairing_requests.lock().unwrap().pending()
+}
 
-    Ok(DashboardStats {
-        up_bps: traffic.up,
-        down_bps: traffic.down,
-        active_connections: connections.connections.unwrap_or_default().len(),
-        memory_mb,
-        core_version: version,
-    })
+#[tauri::command]
+fn confirm_browser_pairing(state: SharedState, request_id: String, approve: bool) -> Result<(), String> {
+    browser_pairing::confirm(&state, &request_id, approve)
 }
 
-fn current_singbox_memory_mb() -> u64 {
-    #[cfg(target_os = "macos")]
-    {
-        return 0;
-    }
+#[tauri::command]
+fn revoke_browser_pairing(state: SharedState, id: String) -> Result<(), String> {
+    browser_pairing::revoke(&state, &id)
+}
 
-    let mut sys = System::new_all();
-    sys.refresh_processes(ProcessesToUpdate::All, false);
+#[tauri::command]
+fn get_browser_api_status(state: SharedState) -> BrowserApiStatus {
+    state.browser_api_status.lock().unwrap().clone()
+}
 
-    let total_kb: u64 = sys
-        .processes()
-        .values()
-        .filter(|p| {
-            let name = p.name().to_string_lossy().to_ascii_lowercase();
-            name == "sing-box" || name == "sing-box.exe"
-        })
-        .map(|p| p.memory())
-        .sum();
+/// Сохраняет порт и перезапускает сервер; тот же порт — повторная попытка после ошибки
+#[tauri::command]
+fn set_browser_api_port(state: SharedState, port: u16) -> Result<(), String> {
+    if port < 1024 {
+        return Err("Порт должен быть в диапазоне 1024–65535".to_string());
+    }
 
-    total_kb / 1024 / 1024
+    {
+        let mut s = state.settings.lock().unwrap();
+        s.browser_api.port = port;
+        s.save(&state.settings_path)?;
+    }
+    state.browser_api_restart.notify_one();
+    Ok(())
 }
 
-async fn wait_for_clash_api(timeout_ms: u64) -> bool {
-    let client = reqwest::Client::new();
-    let secret = "ultunnel-local-secret";
-    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+/// Unit-файл сервиса `ultunnel daemon` для текущей папки данных
+#[cfg(target_os = "linux")]
+#[tauri::command]
+fn get_systemd_unit(state: SharedState, user: bool) -> Result<String, String> {
+    let cli = systemd::cli_binary()?;
+    Ok(systemd::render_unit(user, &cli, &state.data_dir))
+}
 
-    while Instant::now() < deadline {
-        let ok = client
-            .get("http://127.0.0.1:9090/version")
-            .bearer_auth(secret)
-            .send()
-            .await
-            .map(|r| r.status().is_success())
-            .unwrap_or(false);
+#[cfg(target_os = "linux")]
+#[tauri::command]
+fn install_systemd_service(state: SharedState, user: bool, enable: bool) -> Result<String, String> {
+    systemd::install_unit(user, &state.data_dir, enable).map(|p| p.display().to_string())
+}
 
-        if ok {
-            return true;
-        }
+#[cfg(target_os = "linux")]
+#[tauri::command]
+fn uninstall_systemd_service(user: bool) -> Result<String, String> {
+    systemd::uninstall_unit(user).map(|p| p.display().to_string())
+}
 
-        sleep(Duration::from_millis(150));
-    }
+#[tauri::command]
+fn get_quotas(state: SharedState) -> QuotaSettings {
+    state.settings.lock().unwrap().quotas.clone()
+}
 
-    false
+#[tauri::command]
+fn set_quotas(state: SharedState, quotas: QuotaSettings) -> Result<(), String> {
+    let mut s = state.settings.lock().unwrap();
+    s.quotas = quotas;
+    s.save(&state.settings_path)
+}
+
+#[tauri::command]
+fn get_quota_status(state: SharedState) -> Vec<QuotaStatus> {
+    let (quotas, profile) = {
+        let s = state.settings.lock().unwrap();
+        (s.quotas.clone(), s.selected_config.clone())
+    };
+    let usage = state.usage.lock().unwrap();
+    quota::evaluate(&quotas, &usage, profile.as_deref())
 }
+
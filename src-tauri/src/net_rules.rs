@@ -0,0 +1,175 @@
+// Записи раздельной маршрутизации по адресам и портам: IPv4/IPv6-подсети (CIDR)
+// и порты/диапазоны портов. Проверяются при сохранении, в конфиг попадают как ip_cidr/port/port_range.
+
+use std::net::IpAddr;
+
+/// Подсеть в каноническом виде; адрес без маски — /32 или /128
+pub fn normalize_cidr(entry: &str) -> Result<String, String> {
+    let entry = entry.trim();
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((a, p)) => (a.trim(), Some(p.trim())),
+        None => (entry, None),
+    };
+
+    let addr: IpAddr = addr
+        .trim_matches(['[', ']'])
+        .parse()
+        .map_err(|_| "некорректный IP-адрес".to_string())?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+
+    let prefix = match prefix {
+        Some(p) => p
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= max)
+            .ok_or(format!("длина маски должна быть от 0 до {}", max))?,
+        None => max,
+    };
+    Ok(format!("{}/{}", addr, prefix))
+}
+
+/// Попадает ли адрес в подсеть (для проверки маршрута); IPv4 и IPv6 не смешиваются
+pub fn cidr_contains(cidr: &str, ip: IpAddr) -> bool {
+    let Ok(cidr) = normalize_cidr(cidr) else {
+        return false;
+    };
+    let Some((net, prefix)) = cidr.split_once('/') else {
+        return false;
+    };
+    let (Ok(net), Ok(prefix)) = (net.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+        return false;
+    };
+
+    let (net, ip, bits) = match (net, ip) {
+        (IpAddr::V4(n), IpAddr::V4(a)) => (u128::from(u32::from(n)), u128::from(u32::from(a)), 32),
+        (IpAddr::V6(n), IpAddr::V6(a)) => (u128::from(n), u128::from(a), 128),
+        _ => return false,
+    };
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    (net >> shift) == (ip >> shift)
+}
+
+/// Порт или диапазон: "443", "8000-9000" (допускается и "8000:9000", как в sing-box)
+pub fn parse_port_rule(entry: &str) -> Result<(u16, u16), String> {
+    let entry = entry.trim();
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|p| *p > 0)
+            .ok_or(format!("некорректный порт «{}»", s.trim()))
+    };
+
+    match entry.split_once(['-', ':']) {
+        Some((from, to)) => {
+            let (from, to) = (parse(from)?, parse(to)?);
+            if from > to {
+                return Err("начало диапазона больше конца".to_string());
+            }
+            Ok((from, to))
+        }
+        None => parse(entry).map(|p| (p, p)),
+    }
+}
+
+pub fn normalize_port_rule(entry: &str) -> Result<String, String> {
+    let (from, to) = parse_port_rule(entry)?;
+    Ok(if from == to {
+        from.to_string()
+    } else {
+        format!("{}-{}", from, to)
+    })
+}
+
+/// Проверка списка целиком: все ошибки сразу, без пустых записей и повторов
+pub fn normalize_list(
+    entries: &[String],
+    normalize: fn(&str) -> Result<String, String>,
+) -> Result<Vec<String>, String> {
+    let mut result: Vec<String> = vec![];
+    let mut errors = vec![];
+
+    for entry in entries.iter().filter(|e| !e.trim().is_empty()) {
+        match normalize(entry) {
+            Ok(v) => {
+                if !result.contains(&v) {
+                    result.push(v);
+                }
+            }
+            Err(e) => errors.push(format!("«{}»: {}", entry.trim(), e)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(result)
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(a: &str) -> IpAddr {
+        a.parse().unwrap()
+    }
+
+    #[test]
+    fn normalize_cidr_defaults_and_limits() {
+        assert_eq!(normalize_cidr("10.0.0.1").unwrap(), "10.0.0.1/32");
+        assert_eq!(normalize_cidr("2001:db8::1").unwrap(), "2001:db8::1/128");
+        assert_eq!(normalize_cidr("[2001:db8::]/32").unwrap(), "2001:db8::/32");
+        assert_eq!(normalize_cidr(" 0.0.0.0/0 ").unwrap(), "0.0.0.0/0");
+        assert!(normalize_cidr("10.0.0.0/33").is_err());
+        assert!(normalize_cidr("::/129").is_err());
+        assert!(normalize_cidr("example.com").is_err());
+    }
+
+    #[test]
+    fn cidr_contains_prefix_edges() {
+        assert!(cidr_contains("0.0.0.0/0", ip("203.0.113.7")));
+        assert!(cidr_contains("10.0.0.1/32", ip("10.0.0.1")));
+        assert!(!cidr_contains("10.0.0.1/32", ip("10.0.0.2")));
+        assert!(cidr_contains("192.168.0.0/16", ip("192.168.255.255")));
+        assert!(!cidr_contains("192.168.0.0/16", ip("192.169.0.0")));
+
+        assert!(cidr_contains("::/0", ip("2001:db8::1")));
+        assert!(cidr_contains("2001:db8::1/128", ip("2001:db8::1")));
+        assert!(!cidr_contains("2001:db8::1/128", ip("2001:db8::2")));
+        assert!(cidr_contains("[2001:db8::]/32", ip("2001:db8:ffff::1")));
+    }
+
+    #[test]
+    fn cidr_contains_does_not_mix_families() {
+        assert!(!cidr_contains("0.0.0.0/0", ip("::1")));
+        assert!(!cidr_contains("::/0", ip("127.0.0.1")));
+        assert!(!cidr_contains("garbage", ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn parse_port_rule_forms() {
+        assert_eq!(parse_port_rule("443").unwrap(), (443, 443));
+        assert_eq!(parse_port_rule("8000-9000").unwrap(), (8000, 9000));
+        assert_eq!(parse_port_rule("8000:9000").unwrap(), (8000, 9000));
+        assert_eq!(parse_port_rule(" 65535 ").unwrap(), (65535, 65535));
+    }
+
+    #[test]
+    fn parse_port_rule_rejects_invalid() {
+        assert!(parse_port_rule("9000-8000").is_err());
+        assert!(parse_port_rule("0").is_err());
+        assert!(parse_port_rule("0-10").is_err());
+        assert!(parse_port_rule("65536").is_err());
+        assert!(parse_port_rule("http").is_err());
+    }
+
+    #[test]
+    fn normalize_port_rule_uses_dash() {
+        assert_eq!(normalize_port_rule("8000:9000").unwrap(), "8000-9000");
+        assert_eq!(normalize_port_rule("443-443").unwrap(), "443");
+    }
+}
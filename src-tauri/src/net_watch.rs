@@ -0,0 +1,202 @@
+// Переподключение при смене сети на Linux: слушаем netlink (RTMGRP_LINK/ROUTE),
+// и если у системы поменялся маршрут по умолчанию, пока пользователь подключён,
+// проверяем туннель задержкой через Clash API и при неудаче перезапускаем ядро.
+
+use crate::clash_api;
+use crate::AppState;
+use serde::Serialize;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::AppHandle;
+use tauri::Emitter;
+use tokio::sync::mpsc;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Сколько ждать тишины в netlink после последнего события (смена сети даёт пачку сообщений)
+const DEBOUNCE: Duration = Duration::from_secs(2);
+const PROBE_URL: &str = "https://www.gstatic.com/generate_204";
+const PROBE_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReconnectingEvent {
+    status: String,
+    default_route: Vec<String>,
+    error: Option<String>,
+}
+
+/// Маршруты по умолчанию основной таблицы (интерфейс + шлюз), IPv4 и IPv6.
+/// sing-box с auto_route использует свою таблицу, поэтому здесь видна физическая сеть.
+fn default_routes() -> Vec<String> {
+    let mut out = Vec::new();
+
+    if let Ok(s) = fs::read_to_string("/proc/net/route") {
+        for line in s.lines().skip(1) {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+            if cols.len() >= 8 && cols[1] == "00000000" && cols[7] == "00000000" {
+                out.push(format!("{} {}", cols[0], cols[2]));
+            }
+        }
+    }
+
+    if let Ok(s) = fs::read_to_string("/proc/net/ipv6_route") {
+        for line in s.lines() {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            // dest dest_len src src_len next_hop metric refcnt use flags iface
+            if cols.len() >= 10 && cols[1] == "00" && cols[0].chars().all(|c| c == '0') && cols[9] != "lo" {
+                out.push(format!("{} {}", cols[9], cols[4]));
+            }
+        }
+    }
+
+    out.sort();
+    out.dedup();
+    out
+}
+
+fn open_netlink_socket() -> Result<i32, String> {
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        );
+        if fd < 0 {
+            return Err(format!("netlink socket: {}", std::io::Error::last_os_error()));
+        }
+
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups =
+            (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_ROUTE | libc::RTMGRP_IPV6_ROUTE) as u32;
+
+        let r = libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if r < 0 {
+            let e = std::io::Error::last_os_error();
+            libc::close(fd);
+            return Err(format!("netlink bind: {}", e));
+        }
+
+        Ok(fd)
+    }
+}
+
+/// Блокирующее чтение netlink в отдельном потоке; содержимое сообщений не разбираем,
+/// нас интересует только сам факт изменения — маршрут затем читается из /proc.
+fn spawn_netlink_reader(tx: mpsc::Sender<()>) -> Result<(), String> {
+    let fd = open_netlink_socket()?;
+
+    std::thread::Builder::new()
+        .name("netlink-watch".into())
+        .spawn(move || {
+            let mut buf = vec![0u8; 16 * 1024];
+            loop {
+                let n = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+                if n < 0 {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    // ENOBUFS: переполнение очереди — изменения всё равно были
+                    if e.raw_os_error() != Some(libc::ENOBUFS) {
+                        error!("netlink recv: {}", e);
+                        break;
+                    }
+                }
+                // канал на одно событие: если уведомление уже ждёт обработки, этого достаточно
+                if tx.try_send(()).is_err() && tx.is_closed() {
+                    break;
+                }
+            }
+            unsafe { libc::close(fd) };
+        })
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+pub fn spawn_network_watch(app: AppHandle, state: Arc<AppState>) {
+    let (tx, mut rx) = mpsc::channel::<()>(1);
+    if let Err(e) = spawn_netlink_reader(tx) {
+        warn!("Отслеживание смены сети недоступно: {}", e);
+        return;
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut last_routes = default_routes();
+        info!("Отслеживание смены сети запущено, default route: {:?}", last_routes);
+
+        while rx.recv().await.is_some() {
+            // ждём, пока сеть «успокоится»
+            while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {}
+
+            let routes = default_routes();
+            if routes == last_routes {
+                continue;
+            }
+            info!("Маршрут по умолчанию изменился: {:?} -> {:?}", last_routes, routes);
+            last_routes = routes.clone();
+
+            let (enabled, connected) = {
+                let s = state.settings.lock().unwrap();
+                (s.reconnect_on_network_change, s.connected_intent)
+            };
+            if !enabled || !connected || routes.is_empty() {
+                continue;
+            }
+
+            verify_or_restart(&client, &app, &state, routes).await;
+        }
+    });
+}
+
+async fn verify_or_restart(
+    client: &reqwest::Client,
+    app: &AppHandle,
+    state: &Arc<AppState>,
+    routes: Vec<String>,
+) {
+    let running = crate::is_platform_running(state);
+    if running {
+        match clash_api::probe_delay(client, "proxy", PROBE_URL, PROBE_TIMEOUT_MS).await {
+            Ok(ms) => {
+                info!("Туннель работает после смены сети ({} ms)", ms);
+                return;
+            }
+            Err(e) => warn!("Проверка туннеля после смены сети не прошла: {}", e),
+        }
+    }
+
+    emit(app, "restarting", &routes, None);
+    let _ = crate::stop_platform(app.clone(), state).await;
+    match crate::start_platform(app.clone(), state).await {
+        Ok(()) => {
+            info!("Ядро перезапущено после смены сети");
+            emit(app, "restarted", &routes, None);
+        }
+        Err(e) => {
+            error!("Не удалось перезапустить ядро после смены сети: {}", e);
+            emit(app, "failed", &routes, Some(e));
+        }
+    }
+}
+
+fn emit(app: &AppHandle, status: &str, routes: &[String], error: Option<String>) {
+    let event = ReconnectingEvent {
+        status: status.to_string(),
+        default_route: routes.to_vec(),
+        error,
+    };
+    if let Err(e) = app.emit("reconnecting", event) {
+        warn!("Не удалось отправить событие reconnecting: {}", e);
+    }
+}
@@ -0,0 +1,338 @@
+// OpenAPI-описание Browser API (/openapi.json). Схемы повторяют структуры из browser_api.rs,
+// events.rs и browser_pairing.rs — при изменении полей там нужно поправить и здесь.
+
+use serde_json::json;
+use serde_json::Value;
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// Ответ в обёртке ApiResponse с заданной схемой data
+fn envelope(data: Option<&str>) -> Value {
+    let data = match data {
+        Some(name) => schema_ref(name),
+        None => json!({}),
+    };
+    json!({
+        "description": "OK",
+        "content": {
+            "application/json": {
+                "schema": {
+                    "allOf": [
+                        schema_ref("ApiResponse"),
+                        { "type": "object", "properties": { "data": data } }
+                    ]
+                }
+            }
+        }
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema_ref("ApiResponse") } }
+    })
+}
+
+fn json_body(name: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema_ref(name) } }
+    })
+}
+
+fn operation(summary: &str, data: Option<&str>, authorized: bool) -> Value {
+    let mut op = json!({
+        "summary": summary,
+        "responses": {
+            "200": envelope(data),
+            "400": error_response("Некорректный запрос"),
+            "409": error_response("Изменение перекрыто переопределениями выбранного профиля"),
+            "422": error_response("Ошибки валидации по полям (fields)")
+        }
+    });
+    if authorized {
+        op["security"] = json!([{ "bearer": [] }]);
+        op["responses"]["401"] = error_response("Нет действующего токена");
+    }
+    op
+}
+
+fn with_body(mut op: Value, name: &str) -> Value {
+    op["requestBody"] = json_body(name);
+    op
+}
+
+fn with_query(mut op: Value, name: &str, description: &str) -> Value {
+    op["parameters"] = json!([{
+        "name": name,
+        "in": "query",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" }
+    }]);
+    op
+}
+
+fn paths() -> Value {
+    json!({
+        "/health": {
+            "get": operation("Проверка доступности", None, false)
+        },
+        "/openapi.json": {
+            "get": {
+                "summary": "Это описание",
+                "responses": { "200": { "description": "OpenAPI 3.0" } }
+            }
+        },
+        "/proxy.pac": {
+            "get": {
+                "summary": "PAC-файл из списков раздельной маршрутизации",
+                "responses": {
+                    "200": {
+                        "description": "PAC",
+                        "content": { "application/x-ns-proxy-autoconfig": { "schema": { "type": "string" } } }
+                    },
+                    "403": error_response("Запрос со страницы (Origin, Sec-Fetch-Site, Sec-Fetch-Mode) или Host не 127.0.0.1/localhost с портом API")
+                }
+            }
+        },
+        "/pair/request": {
+            "post": with_body(
+                operation("Запрос токена (Origin расширения, подтверждение в приложении)", Some("PairingRequest"), false),
+                "PairRequest"
+            )
+        },
+        "/pair/status": {
+            "get": with_query(
+                operation("Статус запроса; токен отдаётся один раз", Some("PairStatus"), false),
+                "requestId",
+                "id из /pair/request"
+            )
+        },
+        "/state": {
+            "get": with_query(
+                operation("Состояние для текущего сайта", Some("BrowserState"), true),
+                "domain",
+                "Домен сайта (IDN допускается)"
+            )
+        },
+        "/events": {
+            "get": {
+                "summary": "Поток событий state и routing (text/event-stream)",
+                "security": [{ "bearer": [] }],
+                "parameters": [{
+                    "name": "token",
+                    "in": "query",
+                    "required": false,
+                    "description": "Токен для EventSource, который не умеет передавать заголовки",
+                    "schema": { "type": "string" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "SSE: event state — StatusSnapshot, event routing — RoutingSnapshot",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } }
+                    },
+                    "401": error_response("Нет действующего токена")
+                }
+            }
+        },
+        "/domain/toggle": {
+            "post": with_body(
+                operation("Добавить домены в прокси или убрать их оттуда", Some("ToggleDomainResult"), true),
+                "ToggleDomainRequest"
+            )
+        },
+        "/tunnel-all": {
+            "post": with_body(
+                operation("Весь трафик через туннель", Some("RoutingApplied"), true),
+                "TunnelAllRequest"
+            )
+        },
+        "/profiles": {
+            "get": operation("Список профилей", Some("Profiles"), true)
+        },
+        "/profile/select": {
+            "post": with_body(
+                operation("Выбрать профиль", Some("StatusSnapshot"), true),
+                "SelectProfileRequest"
+            )
+        },
+        "/connect": {
+            "post": with_body(
+                operation("Подключиться (можно сразу выбрать профиль)", Some("StatusSnapshot"), true),
+                "ConnectRequest"
+            )
+        },
+        "/disconnect": {
+            "post": operation("Отключиться", Some("StatusSnapshot"), true)
+        },
+        "/stats": {
+            "get": operation("Скорость, соединения, память и версия ядра", Some("DashboardStats"), true)
+        }
+    })
+}
+
+fn schemas() -> Value {
+    let string_list = json!({ "type": "array", "items": { "type": "string" } });
+    let nullable_string = json!({ "type": "string", "nullable": true });
+
+    json!({
+        "ApiResponse": {
+            "type": "object",
+            "required": ["ok"],
+            "properties": {
+                "ok": { "type": "boolean" },
+                "error": { "type": "string" },
+                "data": {},
+                "fields": { "type": "array", "items": schema_ref("FieldError") }
+            }
+        },
+        "FieldError": {
+            "type": "object",
+            "required": ["field", "code", "message"],
+            "properties": {
+                "field": { "type": "string", "example": "domains[1]" },
+                "code": {
+                    "type": "string",
+                    "enum": ["required", "empty", "ip_address", "idna", "too_long", "label_too_long", "invalid_label"]
+                },
+                "message": { "type": "string" }
+            }
+        },
+        "BrowserState": {
+            "type": "object",
+            "properties": {
+                "running": { "type": "boolean" },
+                "siteEnabled": { "type": "boolean" },
+                "tunnelAll": { "type": "boolean" },
+                "domain": { "type": "string", "description": "Домен в punycode без www." },
+                "socks5Enabled": { "type": "boolean" },
+                "selectedProfile": nullable_string,
+                "profileOverride": { "type": "boolean" }
+            }
+        },
+        "ToggleDomainRequest": {
+            "type": "object",
+            "required": ["domain", "enabled"],
+            "properties": {
+                "domain": { "type": "string", "example": "пример.рф" },
+                "domains": string_list,
+                "enabled": { "type": "boolean" }
+            }
+        },
+        "RoutingApplied": {
+            "type": "object",
+            "properties": {
+                "changed": { "type": "boolean" },
+                "running": { "type": "boolean" },
+                "live": { "type": "boolean", "description": "Запущенное ядро уже работает с новыми правилами" },
+                "applyError": { "type": "string" }
+            }
+        },
+        "ToggleDomainResult": {
+            "allOf": [
+                schema_ref("RoutingApplied"),
+                {
+                    "type": "object",
+                    "properties": {
+                        "domains": string_list,
+                        "count": { "type": "integer" }
+                    }
+                }
+            ]
+        },
+        "TunnelAllRequest": {
+            "type": "object",
+            "required": ["enabled"],
+            "properties": { "enabled": { "type": "boolean" } }
+        },
+        "PairRequest": {
+            "type": "object",
+            "properties": { "name": { "type": "string", "description": "Название расширения для окна подтверждения" } }
+        },
+        "PairingRequest": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "origin": { "type": "string" },
+                "name": { "type": "string" },
+                "code": { "type": "string", "description": "Код, который пользователь сверяет с приложением" },
+                "expiresInSecs": { "type": "integer" }
+            }
+        },
+        "PairStatus": {
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["pending", "approved", "denied", "expired"] },
+                "token": { "type": "string" }
+            }
+        },
+        "StatusSnapshot": {
+            "type": "object",
+            "properties": {
+                "running": { "type": "boolean" },
+                "profile": nullable_string,
+                "connectedIntent": { "type": "boolean" }
+            }
+        },
+        "RoutingSnapshot": {
+            "type": "object",
+            "properties": {
+                "enabled": { "type": "boolean" },
+                "tunnelAll": { "type": "boolean" },
+                "socks5Enabled": { "type": "boolean" },
+                "proxyDomains": string_list,
+                "bypassDomains": string_list,
+                "profileOverride": { "type": "boolean", "description": "Списки и режим с учётом переопределений выбранного профиля" }
+            }
+        },
+        "Profiles": {
+            "type": "object",
+            "properties": {
+                "profiles": string_list,
+                "selectedProfile": nullable_string
+            }
+        },
+        "SelectProfileRequest": {
+            "type": "object",
+            "required": ["profile"],
+            "properties": { "profile": { "type": "string" } }
+        },
+        "ConnectRequest": {
+            "type": "object",
+            "properties": { "profile": { "type": "string" } }
+        },
+        "DashboardStats": {
+            "type": "object",
+            "properties": {
+                "upBps": { "type": "integer" },
+                "downBps": { "type": "integer" },
+                "activeConnections": { "type": "integer" },
+                "memoryMb": { "type": "integer" },
+                "coreVersion": nullable_string
+            }
+        }
+    })
+}
+
+pub fn document(port: u16) -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ULtunnel Browser API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Локальный API для браузерного расширения. Токен выдаётся через /pair/request после подтверждения в приложении."
+        },
+        "servers": [{ "url": format!("http://127.0.0.1:{}", port) }],
+        "paths": paths(),
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" }
+            },
+            "schemas": schemas()
+        }
+    })
+}
@@ -0,0 +1,299 @@
+// PAC-файл для браузеров без расширения: доменные правила раздельной маршрутизации
+// проверяются в том же порядке, что и в конфиге ядра (первое совпадение побеждает).
+// proxy — в socks-in, direct и всё остальное — напрямую, block — на заведомо недоступный прокси.
+// Собирается из текущих настроек при каждом запросе.
+// Записи full:/keyword:/regex: переносятся как есть; регулярные выражения sing-box (Go)
+// и JavaScript в простых случаях совпадают, нерабочие выражения PAC пропускает.
+
+use crate::domains;
+use crate::domains::DomainMatch;
+use crate::settings::LocalSettings;
+use crate::settings::RouteAction;
+use crate::settings::RouteRuleKind;
+use crate::settings::RuleList;
+use crate::settings::SplitRoutingSettings;
+use crate::singbox_config::SOCKS5_INBOUND_PORT;
+use serde_json::json;
+use serde_json::Value;
+
+/// Список доменов в виде JS-объекта {full, suffix, keyword, regex}
+fn pac_rules(entries: &[String]) -> Value {
+    let mut full: Vec<String> = vec![];
+    let mut suffix: Vec<String> = vec![];
+    let mut keyword: Vec<String> = vec![];
+    let mut regex: Vec<String> = vec![];
+
+    for entry in entries {
+        let (kind, value) = domains::split_domain_rule(entry);
+        if value.is_empty() {
+            continue;
+        }
+        match kind {
+            DomainMatch::Full => full.push(value.to_ascii_lowercase()),
+            DomainMatch::Suffix => {
+                let d = value.to_ascii_lowercase();
+                let d = d.trim_start_matches("*.").trim_matches('.').to_string();
+                if !d.is_empty() {
+                    suffix.push(d);
+                }
+            }
+            DomainMatch::Keyword => keyword.push(value.to_ascii_lowercase()),
+            DomainMatch::Regex => regex.push(value.to_string()),
+        }
+    }
+
+    for list in [&mut full, &mut suffix, &mut keyword] {
+        list.sort();
+        list.dedup();
+    }
+
+    // JSON — валидный литерал JS, экранирование делает serde_json
+    json!({ "full": full, "suffix": suffix, "keyword": keyword, "regex": regex })
+}
+
+fn has_entries(list: &[String]) -> bool {
+    list.iter().any(|e| !e.trim().is_empty())
+}
+
+/// Ответ PAC для действия правила: block, direct или proxy (ключ TARGETS в JS).
+/// None — правило в PAC не выразить (outbound профиля, которого нет у socks-in)
+fn pac_target(
+    split: &SplitRoutingSettings,
+    action: RouteAction,
+    outbound: Option<&str>,
+    socks: bool,
+) -> Option<&'static str> {
+    // без socks-in отправлять браузер некуда
+    let proxy = if socks { "proxy" } else { "direct" };
+    match action {
+        RouteAction::Block => Some("block"),
+        RouteAction::Direct => Some("direct"),
+        RouteAction::Proxy => Some(proxy),
+        RouteAction::Outbound => match outbound.map(str::trim) {
+            Some(tag) if tag == split.direct_outbound => Some("direct"),
+            Some(tag) if tag == split.proxy_outbound => Some(proxy),
+            _ => None,
+        },
+    }
+}
+
+/// Доменные правила в порядке split.ordered_rules(). IP, порты, приложения и rule-set'ы
+/// браузеру не проверить: такие списки пропускаются, как и собственные правила с портами
+/// или приложениями (без них домен совпал бы шире, чем в ядре)
+fn ordered_pac_rules(split: &SplitRoutingSettings, socks: bool) -> Vec<Value> {
+    let mut rules = vec![];
+    for entry in split.ordered_rules().iter().filter(|r| r.enabled) {
+        let (action, outbound, entries) = match &entry.kind {
+            RouteRuleKind::List { list } => match list {
+                RuleList::BlockDomains => (RouteAction::Block, None, &split.block_domains),
+                RuleList::BypassDomains => (RouteAction::Direct, None, &split.bypass_domains),
+                RuleList::ProxyDomains => (RouteAction::Proxy, None, &split.proxy_domains),
+                _ => continue,
+            },
+            RouteRuleKind::Custom(custom) => {
+                if has_entries(&custom.apps) || has_entries(&custom.ports) {
+                    continue;
+                }
+                (custom.action, custom.outbound.as_deref(), &custom.domains)
+            }
+        };
+
+        if !has_entries(entries) {
+            continue;
+        }
+        let Some(target) = pac_target(split, action, outbound, socks) else {
+            continue;
+        };
+        let mut rule = pac_rules(entries);
+        rule["target"] = json!(target);
+        rules.push(rule);
+    }
+    rules
+}
+
+pub fn render(settings: &LocalSettings) -> String {
+    // с переопределениями выбранного профиля, как в конфиге ядра
+    let split = &settings.active_split_routing();
+    let socks = settings.socks5_inbound;
+
+    // выключенная маршрутизация: всё в туннель (если есть socks-in) без исключений
+    let tunnel_all = socks && !split.enabled;
+    let (rules, final_target) = if split.enabled {
+        let final_tag = split.final_outbound.as_deref();
+        let final_target = pac_target(split, RouteAction::Outbound, final_tag, socks);
+        (ordered_pac_rules(split, socks), final_target.unwrap_or("direct"))
+    } else {
+        (vec![], "direct")
+    };
+
+    format!(
+        r#"// ULtunnel: сгенерировано автоматически из настроек раздельной маршрутизации
+var PROXY = "SOCKS5 127.0.0.1:{port}";
+// порт 9 (discard) обычно закрыт: соединение сразу отклоняется, браузер показывает ошибку
+var BLOCKED = "PROXY 127.0.0.1:9";
+var TARGETS = {{ proxy: PROXY, direct: "DIRECT", block: BLOCKED }};
+var TUNNEL_ALL = {tunnel_all};
+// правила в порядке настроек: первое совпадение побеждает
+var RULES = compileRules({rules});
+var FINAL = TARGETS["{final_target}"];
+
+function compileRules(rules) {{
+	for (var r = 0; r < rules.length; r++) {{
+		var compiled = [];
+		for (var i = 0; i < rules[r].regex.length; i++) {{
+			try {{
+				compiled.push(new RegExp(rules[r].regex[i]));
+			}} catch (e) {{
+			}}
+		}}
+		rules[r].regex = compiled;
+	}}
+	return rules;
+}}
+
+function matchesRules(host, rules) {{
+	var i;
+	for (i = 0; i < rules.full.length; i++) {{
+		if (host === rules.full[i]) return true;
+	}}
+	for (i = 0; i < rules.suffix.length; i++) {{
+		var d = rules.suffix[i];
+		if (host === d) return true;
+		if (host.length > d.length && host.substring(host.length - d.length - 1) === "." + d) return true;
+	}}
+	for (i = 0; i < rules.keyword.length; i++) {{
+		if (host.indexOf(rules.keyword[i]) >= 0) return true;
+	}}
+	for (i = 0; i < rules.regex.length; i++) {{
+		if (rules.regex[i].test(host)) return true;
+	}}
+	return false;
+}}
+
+function FindProxyForURL(url, host) {{
+	host = host.toLowerCase();
+	if (isPlainHostName(host) || host === "localhost" || host === "127.0.0.1" || host === "[::1]") return "DIRECT";
+	if (TUNNEL_ALL) return PROXY;
+	for (var i = 0; i < RULES.length; i++) {{
+		if (matchesRules(host, RULES[i])) return TARGETS[RULES[i].target];
+	}}
+	return FINAL;
+}}
+"#,
+        port = SOCKS5_INBOUND_PORT,
+        tunnel_all = tunnel_all,
+        rules = Value::Array(rules),
+        final_target = final_target,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::CustomRule;
+    use crate::settings::RouteRule;
+
+    fn list(list: RuleList) -> RouteRule {
+        RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::List { list },
+        }
+    }
+
+    fn custom(rule: CustomRule) -> RouteRule {
+        RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::Custom(rule),
+        }
+    }
+
+    fn settings(split: SplitRoutingSettings, socks: bool) -> LocalSettings {
+        LocalSettings {
+            split_routing: split,
+            socks5_inbound: socks,
+            ..Default::default()
+        }
+    }
+
+    fn var<'a>(pac: &'a str, name: &str) -> &'a str {
+        let prefix = format!("var {} = ", name);
+        let line = pac.lines().find(|l| l.starts_with(&prefix)).unwrap();
+        line[prefix.len()..].trim_end_matches(';')
+    }
+
+    /// Ответы правил PAC по порядку
+    fn targets(pac: &str) -> Vec<String> {
+        let rules = var(pac, "RULES")
+            .trim_start_matches("compileRules(")
+            .trim_end_matches(')');
+        let rules: Vec<Value> = serde_json::from_str(rules).unwrap();
+        rules
+            .iter()
+            .map(|r| r["target"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn split() -> SplitRoutingSettings {
+        SplitRoutingSettings {
+            enabled: true,
+            block_domains: vec!["ads.example.com".into()],
+            proxy_domains: vec!["youtube.com".into()],
+            bypass_domains: vec!["example.com".into()],
+            rules: vec![
+                list(RuleList::ProxyDomains),
+                // с портом правило в PAC не выразить
+                custom(CustomRule {
+                    domains: vec!["keyword:tracker".into()],
+                    ports: vec!["443".into()],
+                    action: RouteAction::Block,
+                    ..Default::default()
+                }),
+                custom(CustomRule {
+                    domains: vec!["full:cdn.example.com".into()],
+                    action: RouteAction::Outbound,
+                    outbound: Some("proxy".into()),
+                    ..Default::default()
+                }),
+                list(RuleList::BypassDomains),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rules_follow_user_order() {
+        let pac = render(&settings(split(), true));
+        assert_eq!(targets(&pac), ["block", "proxy", "proxy", "direct"]);
+        assert_eq!(var(&pac, "TUNNEL_ALL"), "false");
+        assert_eq!(var(&pac, "FINAL"), r#"TARGETS["direct"]"#);
+    }
+
+    #[test]
+    fn proxy_rules_go_direct_without_socks_inbound() {
+        let mut split = split();
+        split.final_outbound = Some("proxy".into());
+
+        let pac = render(&settings(split, false));
+        assert_eq!(targets(&pac), ["block", "direct", "direct", "direct"]);
+        assert_eq!(var(&pac, "FINAL"), r#"TARGETS["direct"]"#);
+    }
+
+    #[test]
+    fn final_outbound_is_used_for_unmatched_hosts() {
+        let mut split = split();
+        split.final_outbound = Some("proxy".into());
+
+        let pac = render(&settings(split, true));
+        assert_eq!(var(&pac, "FINAL"), r#"TARGETS["proxy"]"#);
+    }
+
+    #[test]
+    fn disabled_routing_tunnels_everything() {
+        let mut split = split();
+        split.enabled = false;
+
+        let pac = render(&settings(split, true));
+        assert!(targets(&pac).is_empty());
+        assert_eq!(var(&pac, "TUNNEL_ALL"), "true");
+    }
+}
@@ -0,0 +1,68 @@
+// Список профилей: загрузка с сервера по access_key и хранение в configs.json
+
+use crate::api::fetch_raw_configs;
+use crate::api::normalize_configs;
+use crate::api::ProxyConfig;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+pub fn configs_path_from_settings(settings_path: &Path) -> PathBuf {
+    settings_path.with_file_name("configs.json")
+}
+
+pub fn load_configs_from_file(path: &Path) -> Vec<ProxyConfig> {
+    if let Ok(s) = fs::read_to_string(path) {
+        serde_json::from_str::<Vec<ProxyConfig>>(&s).unwrap_or_default()
+    } else {
+        Vec::new()
+    }
+}
+
+pub fn save_configs_to_file(path: &Path, configs: &[ProxyConfig]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(configs).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+/// Загрузка и нормализация профилей с сервера
+pub async fn fetch_configs(access_key: &str) -> Result<Vec<ProxyConfig>, String> {
+    if access_key.is_empty() {
+        warn!("Попытка загрузить конфиги без access_key");
+        return Err("accessKey не задан".into());
+    }
+
+    info!("Начата загрузка конфигов");
+
+    let raw = match fetch_raw_configs(access_key).await {
+        Ok(v) => {
+            info!("Сырые конфиги успешно загружены");
+            v
+        }
+        Err(e) => {
+            error!("Ошибка загрузки конфигов: {}", e);
+            return Err(e);
+        }
+    };
+
+    match normalize_configs(raw) {
+        Ok(v) => {
+            info!("Конфиги успешно нормализованы, count={}", v.len());
+            Ok(v)
+        }
+        Err(e) => {
+            error!("Ошибка нормализации конфигов: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Профиль по имени
+pub fn find_config<'a>(configs: &'a [ProxyConfig], name: &str) -> Option<&'a ProxyConfig> {
+    configs.iter().find(|c| c.name == name)
+}
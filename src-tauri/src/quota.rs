@@ -0,0 +1,374 @@
+use crate::settings::QuotaAction;
+use crate::settings::QuotaLimit;
+use crate::settings::QuotaSettings;
+use crate::traffic_usage::day_key;
+use crate::traffic_usage::month_key;
+use crate::traffic_usage::TrafficUsage;
+use crate::traffic_usage::UsageBucket;
+use crate::AppState;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tauri::AppHandle;
+use tauri::Emitter;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QuotaScope {
+    Global,
+    Profile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QuotaPeriod {
+    Day,
+    Month,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaStatus {
+    pub scope: QuotaScope,
+    pub profile: Option<String>,
+    pub period: QuotaPeriod,
+    // YYYY-MM-DD или YYYY-MM
+    pub period_key: String,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+    pub percent: u64,
+}
+
+impl QuotaStatus {
+    pub fn exceeded(&self) -> bool {
+        self.used_bytes >= self.limit_bytes
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuotaWarningEvent {
+    #[serde(flatten)]
+    status: QuotaStatus,
+    threshold: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuotaActionEvent {
+    action: QuotaAction,
+    profile: Option<String>,
+    error: Option<String>,
+}
+
+/// Какие предупреждения уже отправлены (чтобы не слать их каждую секунду)
+#[derive(Default)]
+pub struct QuotaTracker {
+    notified: HashSet<String>,
+    acting: bool,
+}
+
+fn push_status(
+    out: &mut Vec<QuotaStatus>,
+    scope: QuotaScope,
+    profile: Option<&str>,
+    limit: &QuotaLimit,
+    today: &UsageBucket,
+    month: &UsageBucket,
+) {
+    let periods = [
+        (QuotaPeriod::Day, day_key(), limit.daily_bytes, today),
+        (QuotaPeriod::Month, month_key(), limit.monthly_bytes, month),
+    ];
+
+    for (period, period_key, limit_bytes, bucket) in periods {
+        let Some(limit_bytes) = limit_bytes.filter(|v| *v > 0) else {
+            continue;
+        };
+        let used_bytes = bucket.bytes(match scope {
+            QuotaScope::Global => None,
+            QuotaScope::Profile => profile,
+        });
+
+        out.push(QuotaStatus {
+            scope,
+            profile: profile.map(|p| p.to_string()),
+            period,
+            period_key,
+            used_bytes,
+            limit_bytes,
+            percent: used_bytes.saturating_mul(100) / limit_bytes,
+        });
+    }
+}
+
+/// Состояние всех лимитов, применимых к профилю
+pub fn evaluate(quotas: &QuotaSettings, usage: &TrafficUsage, profile: Option<&str>) -> Vec<QuotaStatus> {
+    let today = usage.today();
+    let month = usage.this_month();
+    let mut out = Vec::new();
+
+    push_status(&mut out, QuotaScope::Global, profile, &quotas.global, &today, &month);
+
+    if let Some(p) = profile {
+        if let Some(limit) = quotas.profiles.get(p) {
+            push_status(&mut out, QuotaScope::Profile, Some(p), limit, &today, &month);
+        }
+    }
+
+    out
+}
+
+fn notification_key(status: &QuotaStatus, threshold: u8) -> String {
+    format!(
+        "{:?}:{}:{:?}:{}:{}",
+        status.scope,
+        status.profile.as_deref().unwrap_or(""),
+        status.period,
+        status.period_key,
+        threshold
+    )
+}
+
+/// Проверка лимитов; вызывается из подписки на трафик раз в секунду
+pub fn check(app: &AppHandle, state: &Arc<AppState>) {
+    let (quotas, profile) = {
+        let s = state.settings.lock().unwrap();
+        (s.quotas.clone(), s.selected_config.clone())
+    };
+    if !quotas.enabled {
+        return;
+    }
+
+    let statuses = {
+        let usage = state.usage.lock().unwrap();
+        evaluate(&quotas, &usage, profile.as_deref())
+    };
+
+    let mut thresholds: Vec<u8> = quotas
+        .warn_thresholds
+        .iter()
+        .copied()
+        .filter(|t| *t > 0 && *t < 100)
+        .collect();
+    thresholds.sort();
+    thresholds.dedup();
+
+    let mut exceeded = Vec::new();
+    {
+        let mut tracker = state.quota.lock().unwrap();
+        for status in &statuses {
+            for t in &thresholds {
+                if status.percent >= *t as u64 && tracker.notified.insert(notification_key(status, *t)) {
+                    emit(app, "quota-warning", QuotaWarningEvent {
+                        status: status.clone(),
+                        threshold: *t,
+                    });
+                }
+            }
+
+            if status.exceeded() {
+                if tracker.notified.insert(notification_key(status, 100)) {
+                    warn!(
+                        "Превышен лимит трафика: {:?} {:?} {:?} {}/{}",
+                        status.scope, status.profile, status.period, status.used_bytes, status.limit_bytes
+                    );
+                    emit(app, "quota-exceeded", status.clone());
+                }
+                exceeded.push(status.clone());
+            }
+        }
+
+        if exceeded.is_empty() || quotas.action == QuotaAction::Warn || tracker.acting {
+            return;
+        }
+        if !crate::is_platform_running(state) {
+            return;
+        }
+        tracker.acting = true;
+    }
+
+    let app = app.clone();
+    let state = state.clone();
+    tauri::async_runtime::spawn(async move {
+        enforce(&app, &state, &quotas, profile, &exceeded).await;
+        state.quota.lock().unwrap().acting = false;
+    });
+}
+
+async fn enforce(
+    app: &AppHandle,
+    state: &Arc<AppState>,
+    quotas: &QuotaSettings,
+    current: Option<String>,
+    exceeded: &[QuotaStatus],
+) {
+    // при превышении общего лимита переключение не поможет — только остановка
+    let only_profile_limits = exceeded.iter().all(|s| s.scope == QuotaScope::Profile);
+    let target = if quotas.action == QuotaAction::Switch && only_profile_limits {
+        pick_switch_target(state, quotas, current.as_deref())
+    } else {
+        None
+    };
+
+    let event = match target {
+        Some(name) => {
+            info!("Лимит трафика профиля исчерпан, переключение на {}", name);
+            {
+                let mut s = state.settings.lock().unwrap();
+                s.selected_config = Some(name.clone());
+                if let Err(e) = s.save(&state.settings_path) {
+                    error!("Ошибка сохранения config.json: {}", e);
+                }
+            }
+            let _ = crate::stop_platform(app.clone(), state).await;
+            let r = crate::start_platform(app.clone(), state).await;
+            QuotaActionEvent {
+                action: QuotaAction::Switch,
+                profile: Some(name),
+                error: r.err(),
+            }
+        }
+        None => {
+            info!("Лимит трафика исчерпан, sing-box будет остановлен");
+            let r = crate::disconnect(app.clone(), state).await;
+            QuotaActionEvent {
+                action: QuotaAction::Stop,
+                profile: current,
+                error: r.err(),
+            }
+        }
+    };
+
+    emit(app, "quota-action", event);
+}
+
+fn pick_switch_target(state: &Arc<AppState>, quotas: &QuotaSettings, current: Option<&str>) -> Option<String> {
+    let names: Vec<String> = state
+        .configs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|c| c.name.clone())
+        .collect();
+
+    let candidates = quotas
+        .fallback_profile
+        .iter()
+        .filter(|p| names.contains(p))
+        .chain(names.iter());
+
+    let usage = state.usage.lock().unwrap();
+    candidates
+        .filter(|p| Some(p.as_str()) != current)
+        .find(|p| {
+            !evaluate(quotas, &usage, Some(p.as_str()))
+                .iter()
+                .any(|s| s.exceeded())
+        })
+        .cloned()
+}
+
+fn emit<S: Serialize + Clone>(app: &AppHandle, event: &str, payload: S) {
+    if let Err(e) = app.emit(event, payload) {
+        warn!("Не удалось отправить событие {}: {}", event, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn usage(records: &[(&str, u64, u64)]) -> TrafficUsage {
+        // файла нет — пустая статистика; на диск в тестах ничего не пишется
+        let mut usage = TrafficUsage::load(Path::new("/nonexistent/traffic-usage.json"));
+        for (profile, up, down) in records {
+            usage.record(Some(profile), *up, *down);
+        }
+        usage
+    }
+
+    fn limit(daily: Option<u64>, monthly: Option<u64>) -> QuotaLimit {
+        QuotaLimit {
+            daily_bytes: daily,
+            monthly_bytes: monthly,
+        }
+    }
+
+    #[test]
+    fn no_limits_no_statuses() {
+        let quotas = QuotaSettings::default();
+        assert!(evaluate(&quotas, &usage(&[("a", 10, 10)]), Some("a")).is_empty());
+    }
+
+    #[test]
+    fn zero_limit_is_ignored() {
+        let quotas = QuotaSettings {
+            global: limit(Some(0), None),
+            ..Default::default()
+        };
+        assert!(evaluate(&quotas, &usage(&[("a", 10, 10)]), Some("a")).is_empty());
+    }
+
+    #[test]
+    fn global_limit_counts_all_profiles() {
+        let quotas = QuotaSettings {
+            global: limit(Some(1000), Some(10_000)),
+            ..Default::default()
+        };
+        let statuses = evaluate(
+            &quotas,
+            &usage(&[("a", 300, 300), ("b", 200, 200)]),
+            Some("a"),
+        );
+
+        assert_eq!(statuses.len(), 2);
+        let day = &statuses[0];
+        assert_eq!(day.scope, QuotaScope::Global);
+        assert_eq!(day.period, QuotaPeriod::Day);
+        assert_eq!(day.period_key, day_key());
+        assert_eq!(day.used_bytes, 1000);
+        assert_eq!(day.percent, 100);
+        assert!(day.exceeded());
+
+        let month = &statuses[1];
+        assert_eq!(month.period, QuotaPeriod::Month);
+        assert_eq!(month.percent, 10);
+        assert!(!month.exceeded());
+    }
+
+    #[test]
+    fn profile_limit_counts_only_that_profile() {
+        let mut quotas = QuotaSettings::default();
+        quotas
+            .profiles
+            .insert("a".to_string(), limit(None, Some(4000)));
+        let usage = usage(&[("a", 500, 500), ("b", 3000, 3000)]);
+
+        let statuses = evaluate(&quotas, &usage, Some("a"));
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].scope, QuotaScope::Profile);
+        assert_eq!(statuses[0].profile.as_deref(), Some("a"));
+        assert_eq!(statuses[0].used_bytes, 1000);
+        assert_eq!(statuses[0].percent, 25);
+
+        // у другого профиля своего лимита нет
+        assert!(evaluate(&quotas, &usage, Some("b")).is_empty());
+        assert!(evaluate(&quotas, &usage, None).is_empty());
+    }
+
+    #[test]
+    fn percent_does_not_overflow() {
+        let quotas = QuotaSettings {
+            global: limit(Some(1), None),
+            ..Default::default()
+        };
+        let statuses = evaluate(&quotas, &usage(&[("a", u64::MAX, 0)]), None);
+        assert_eq!(statuses[0].used_bytes, u64::MAX);
+        assert!(statuses[0].exceeded());
+    }
+}
@@ -0,0 +1,465 @@
+// Проверка маршрута: какое правило из route.rules сгенерированного конфига сработает для
+// домена/IP/порта/процесса. Условия сравниваются так же, как в sing-box: домены и подсети
+// по ИЛИ, порты по ИЛИ между собой и по И с остальным, process_name/process_path по И.
+// То, что без реального соединения не проверить (rule_set, protocol, source_*, logical),
+// считается несовпавшим и попадает в notes.
+
+use crate::domains;
+use crate::net_rules;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
+use std::net::IpAddr;
+
+/// inbound, через который приходит трафик приложений
+const DEFAULT_INBOUND: &str = "tun-in";
+
+/// Поля правила, которые здесь не вычисляются
+const UNSUPPORTED_FIELDS: [&str; 10] = [
+    "rule_set",
+    "protocol",
+    "network",
+    "source_ip_cidr",
+    "source_port",
+    "source_port_range",
+    "ip_is_private",
+    "geosite",
+    "geoip",
+    "user",
+];
+
+/// Условия по адресу назначения, объединяемые по ИЛИ
+const ADDRESS_FIELDS: [&str; 5] = [
+    "domain",
+    "domain_suffix",
+    "domain_keyword",
+    "domain_regex",
+    "ip_cidr",
+];
+
+/// Действия, после которых sing-box продолжает перебор правил
+const NON_FINAL_ACTIONS: [&str; 4] = ["sniff", "hijack-dns", "resolve", "route-options"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteQuery {
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    // имя процесса или полный путь к исполняемому файлу
+    #[serde(default)]
+    pub process: Option<String>,
+    #[serde(default)]
+    pub inbound: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteMatch {
+    /// номер правила в route.rules; None — сработал route.final
+    pub index: Option<usize>,
+    pub rule: Option<String>,
+    /// тег outbound или "reject"
+    pub outbound: String,
+    pub notes: Vec<String>,
+}
+
+struct Destination {
+    domain: Option<String>,
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+    process_name: Option<String>,
+    process_path: Option<String>,
+    inbound: String,
+}
+
+fn non_empty(v: &Option<String>) -> Option<&str> {
+    v.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn destination(query: &RouteQuery) -> Result<Destination, String> {
+    let mut domain = None;
+    let mut ip = None;
+
+    if let Some(d) = non_empty(&query.domain) {
+        // IP в поле домена — тоже допустимо
+        match d.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(a) => ip = Some(a),
+            Err(_) => domain = Some(domains::normalize_hostname(d).map_err(|e| e.to_string())?),
+        }
+    }
+    if let Some(a) = non_empty(&query.ip) {
+        ip = Some(
+            a.trim_matches(['[', ']'])
+                .parse::<IpAddr>()
+                .map_err(|_| "Некорректный IP-адрес".to_string())?,
+        );
+    }
+
+    let (process_name, process_path) = match non_empty(&query.process) {
+        Some(p) if p.contains('/') || p.contains('\\') => {
+            let name = p.rsplit(['/', '\\']).next().unwrap_or(p).to_string();
+            (Some(name), Some(p.to_string()))
+        }
+        Some(p) => (Some(p.to_string()), None),
+        None => (None, None),
+    };
+
+    if domain.is_none() && ip.is_none() && query.port.is_none() && process_name.is_none() {
+        return Err("Укажите домен, IP, порт или процесс".to_string());
+    }
+
+    Ok(Destination {
+        domain,
+        ip,
+        port: query.port,
+        process_name,
+        process_path,
+        inbound: non_empty(&query.inbound).unwrap_or(DEFAULT_INBOUND).to_string(),
+    })
+}
+
+fn strings(rule: &Map<String, Value>, field: &str) -> Option<Vec<String>> {
+    match rule.get(field)? {
+        Value::String(s) => Some(vec![s.clone()]),
+        Value::Array(items) => Some(
+            items
+                .iter()
+                .filter_map(|v| match v {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        Value::Number(n) => Some(vec![n.to_string()]),
+        _ => None,
+    }
+}
+
+/// domain_suffix в sing-box: ".example.com" — только поддомены, "example.com" — и сам домен
+fn suffix_matches(host: &str, suffix: &str) -> bool {
+    let suffix = suffix.to_ascii_lowercase();
+    if let Some(dotted) = suffix.strip_prefix('.') {
+        return host.ends_with(&format!(".{}", dotted));
+    }
+    host == suffix || host.ends_with(&format!(".{}", suffix))
+}
+
+/// Диапазон в sing-box: "1000:2000", ":2000", "1000:"
+fn port_range_matches(range: &str, port: u16) -> bool {
+    let Some((from, to)) = range.split_once(':') else {
+        return false;
+    };
+    let from = if from.is_empty() { Some(0) } else { from.parse::<u16>().ok() };
+    let to = if to.is_empty() { Some(u16::MAX) } else { to.parse::<u16>().ok() };
+    matches!((from, to), (Some(f), Some(t)) if f <= port && port <= t)
+}
+
+fn address_matches(field: &str, value: &str, dst: &Destination) -> bool {
+    let host = dst.domain.as_deref();
+    match field {
+        "domain" => host == Some(value.to_ascii_lowercase().as_str()),
+        "domain_suffix" => host.is_some_and(|h| suffix_matches(h, value)),
+        "domain_keyword" => host.is_some_and(|h| h.contains(&value.to_ascii_lowercase())),
+        "domain_regex" => host.is_some_and(|h| Regex::new(value).is_ok_and(|re| re.is_match(h))),
+        "ip_cidr" => dst.ip.is_some_and(|ip| net_rules::cidr_contains(value, ip)),
+        _ => false,
+    }
+}
+
+/// Совпадение без учёта invert и действия
+fn conditions_match(rule: &Map<String, Value>, dst: &Destination) -> bool {
+    if let Some(inbounds) = strings(rule, "inbound") {
+        if !inbounds.contains(&dst.inbound) {
+            return false;
+        }
+    }
+
+    // домены и подсети
+    let mut address_fields = false;
+    let mut address_match = false;
+    for field in ADDRESS_FIELDS {
+        if let Some(values) = strings(rule, field) {
+            address_fields = true;
+            address_match |= values.iter().any(|v| address_matches(field, v, dst));
+        }
+    }
+    if address_fields && !address_match {
+        return false;
+    }
+
+    // порты
+    let ports = strings(rule, "port");
+    let ranges = strings(rule, "port_range");
+    if ports.is_some() || ranges.is_some() {
+        let Some(port) = dst.port else {
+            return false;
+        };
+        let in_ports = ports.iter().flatten().any(|p| p.parse::<u16>() == Ok(port));
+        let in_ranges = ranges.iter().flatten().any(|r| port_range_matches(r, port));
+        if !in_ports && !in_ranges {
+            return false;
+        }
+    }
+
+    if let Some(names) = strings(rule, "process_name") {
+        if !dst.process_name.as_ref().is_some_and(|n| names.contains(n)) {
+            return false;
+        }
+    }
+    if let Some(paths) = strings(rule, "process_path") {
+        if !dst.process_path.as_ref().is_some_and(|p| paths.contains(p)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Куда отправляет правило: Some(outbound) — перебор заканчивается, None — продолжается
+fn rule_outcome(rule: &Map<String, Value>) -> Option<String> {
+    let action = rule.get("action").and_then(|v| v.as_str()).unwrap_or("route");
+    if NON_FINAL_ACTIONS.contains(&action) {
+        return None;
+    }
+    if action == "reject" {
+        return Some("reject".to_string());
+    }
+    rule.get("outbound").and_then(|v| v.as_str()).map(str::to_string)
+}
+
+fn default_outbound(cfg: &Value) -> String {
+    cfg.get("route")
+        .and_then(|r| r.get("final"))
+        .and_then(|v| v.as_str())
+        .or_else(|| {
+            cfg.get("outbounds")
+                .and_then(|v| v.as_array())
+                .and_then(|list| list.first())
+                .and_then(|o| o.get("tag"))
+                .and_then(|v| v.as_str())
+        })
+        .unwrap_or("direct")
+        .to_string()
+}
+
+/// Проходит route.rules конфига по порядку и возвращает первое окончательное совпадение
+pub fn evaluate(cfg: &Value, query: &RouteQuery) -> Result<RouteMatch, String> {
+    let dst = destination(query)?;
+    let mut notes: Vec<String> = vec![];
+
+    let rules = cfg
+        .get("route")
+        .and_then(|r| r.get("rules"))
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    for (index, rule) in rules.iter().enumerate() {
+        let Some(rule) = rule.as_object() else {
+            continue;
+        };
+
+        // sniff, hijack-dns и т.п. маршрут не выбирают
+        let Some(outbound) = rule_outcome(rule) else {
+            continue;
+        };
+
+        if rule.get("type").and_then(|v| v.as_str()) == Some("logical") {
+            notes.push(format!("Правило {}: логические правила не проверяются", index));
+            continue;
+        }
+        let unsupported: Vec<&str> = UNSUPPORTED_FIELDS
+            .iter()
+            .copied()
+            .filter(|f| rule.contains_key(*f))
+            .collect();
+        if !unsupported.is_empty() {
+            notes.push(format!("Правило {}: не проверяется ({})", index, unsupported.join(", ")));
+            continue;
+        }
+
+        let invert = rule.get("invert").and_then(|v| v.as_bool()).unwrap_or(false);
+        if conditions_match(rule, &dst) == invert {
+            continue;
+        }
+
+        return Ok(RouteMatch {
+            index: Some(index),
+            rule: Some(Value::Object(rule.clone()).to_string()),
+            outbound,
+            notes,
+        });
+    }
+
+    Ok(RouteMatch {
+        index: None,
+        rule: None,
+        outbound: default_outbound(cfg),
+        notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(rules: Value) -> Value {
+        json!({
+            "outbounds": [{ "tag": "proxy" }, { "tag": "direct" }],
+            "route": { "rules": rules, "final": "direct" },
+        })
+    }
+
+    fn domain(d: &str) -> RouteQuery {
+        RouteQuery {
+            domain: Some(d.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn ip(a: &str) -> RouteQuery {
+        RouteQuery {
+            ip: Some(a.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn port(p: u16) -> RouteQuery {
+        RouteQuery {
+            port: Some(p),
+            ..Default::default()
+        }
+    }
+
+    fn process(p: &str) -> RouteQuery {
+        RouteQuery {
+            process: Some(p.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Номер сработавшего правила, None — route.final
+    fn matched(rules: Value, query: RouteQuery) -> Option<usize> {
+        evaluate(&config(rules), &query).unwrap().index
+    }
+
+    #[test]
+    fn suffix_with_leading_dot_matches_only_subdomains() {
+        let rules = json!([{ "domain_suffix": [".example.com"], "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), domain("a.example.com")), Some(0));
+        assert_eq!(matched(rules.clone(), domain("example.com")), None);
+        assert_eq!(matched(rules, domain("badexample.com")), None);
+    }
+
+    #[test]
+    fn suffix_without_dot_matches_domain_and_subdomains() {
+        let rules = json!([{ "domain_suffix": ["example.com"], "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), domain("example.com")), Some(0));
+        assert_eq!(matched(rules.clone(), domain("a.b.example.com")), Some(0));
+        assert_eq!(matched(rules, domain("badexample.com")), None);
+    }
+
+    #[test]
+    fn keyword_ignores_case() {
+        let rules = json!([{ "domain_keyword": ["Google"], "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), domain("www.google.com")), Some(0));
+        assert_eq!(matched(rules, domain("example.com")), None);
+    }
+
+    #[test]
+    fn cidr_boundaries() {
+        let rules = json!([{ "ip_cidr": ["10.0.0.0/24"], "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), ip("10.0.0.0")), Some(0));
+        assert_eq!(matched(rules.clone(), ip("10.0.0.255")), Some(0));
+        assert_eq!(matched(rules, ip("10.0.1.0")), None);
+    }
+
+    #[test]
+    fn cidr_does_not_mix_ipv4_and_ipv6() {
+        let v6 = json!([{ "ip_cidr": ["::/0"], "outbound": "proxy" }]);
+        assert_eq!(matched(v6.clone(), ip("1.2.3.4")), None);
+        assert_eq!(matched(v6, ip("2001:db8::1")), Some(0));
+
+        let v4 = json!([{ "ip_cidr": ["0.0.0.0/0"], "outbound": "proxy" }]);
+        assert_eq!(matched(v4.clone(), ip("[::1]")), None);
+        assert_eq!(matched(v4, ip("1.2.3.4")), Some(0));
+    }
+
+    #[test]
+    fn port_and_port_range_are_or() {
+        let rules = json!([{ "port": [443], "port_range": ["8000:9000"], "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), port(443)), Some(0));
+        assert_eq!(matched(rules.clone(), port(8500)), Some(0));
+        assert_eq!(matched(rules, port(80)), None);
+    }
+
+    #[test]
+    fn process_name_and_path() {
+        let by_name = json!([{ "process_name": ["firefox"], "outbound": "proxy" }]);
+        assert_eq!(matched(by_name.clone(), process("firefox")), Some(0));
+        assert_eq!(matched(by_name, process("/usr/bin/firefox")), Some(0));
+
+        let by_path = json!([{ "process_path": ["/usr/bin/firefox"], "outbound": "proxy" }]);
+        assert_eq!(
+            matched(by_path.clone(), process("/usr/bin/firefox")),
+            Some(0)
+        );
+        assert_eq!(matched(by_path.clone(), process("firefox")), None);
+        assert_eq!(matched(by_path, process("/opt/firefox/firefox")), None);
+    }
+
+    #[test]
+    fn invert_negates_conditions() {
+        let rules =
+            json!([{ "domain_suffix": ["example.com"], "invert": true, "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), domain("other.org")), Some(0));
+        assert_eq!(matched(rules, domain("example.com")), None);
+    }
+
+    #[test]
+    fn sniff_and_hijack_dns_do_not_select_route() {
+        let rules = json!([
+            { "inbound": ["tun-in"], "action": "sniff" },
+            { "protocol": ["dns"], "action": "hijack-dns" },
+            { "domain": ["example.com"], "action": "reject" },
+        ]);
+        let m = evaluate(&config(rules), &domain("example.com")).unwrap();
+        assert_eq!(m.index, Some(2));
+        assert_eq!(m.outbound, "reject");
+        assert!(m.notes.is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_final() {
+        let rules = json!([{ "domain": ["example.com"], "outbound": "proxy" }]);
+        let m = evaluate(&config(rules), &domain("other.org")).unwrap();
+        assert_eq!(m.index, None);
+        assert_eq!(m.outbound, "direct");
+
+        // без route.final — первый outbound
+        let cfg = json!({ "outbounds": [{ "tag": "proxy" }], "route": { "rules": [] } });
+        assert_eq!(
+            evaluate(&cfg, &domain("other.org")).unwrap().outbound,
+            "proxy"
+        );
+    }
+
+    #[test]
+    fn inbound_filters_rules() {
+        let rules = json!([{ "inbound": ["socks-in"], "outbound": "proxy" }]);
+        assert_eq!(matched(rules.clone(), domain("example.com")), None);
+
+        let socks = RouteQuery {
+            inbound: Some("socks-in".to_string()),
+            ..domain("example.com")
+        };
+        assert_eq!(matched(rules, socks), Some(0));
+    }
+}
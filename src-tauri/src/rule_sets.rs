@@ -0,0 +1,263 @@
+// Rule-set'ы sing-box в раздельной маршрутизации. Локальные файлы подключаются как есть,
+// удалённые приложение скачивает само в <data_dir>/rule-sets и подключает как локальные:
+// ядро стартует без сети, а при недоступном URL используется последняя скачанная копия.
+
+use crate::settings::RuleSetRef;
+use crate::settings::SplitRoutingSettings;
+use crate::AppState;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::AppHandle;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+const CACHE_DIR: &str = "rule-sets";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// Как часто фоновая задача проверяет, не устарели ли скачанные rule-set'ы
+const CHECK_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+const FORMAT_BINARY: &str = "binary";
+const FORMAT_SOURCE: &str = "source";
+/// Сигнатура бинарного rule-set'а sing-box
+const SRS_MAGIC: &[u8] = b"SRS";
+
+fn non_empty(v: &Option<String>) -> Option<&str> {
+    v.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// binary (.srs) или source (.json): явно заданный формат, иначе по расширению
+pub fn format_of(rs: &RuleSetRef) -> &'static str {
+    match rs.format.as_deref() {
+        Some(FORMAT_SOURCE) => return FORMAT_SOURCE,
+        Some(FORMAT_BINARY) => return FORMAT_BINARY,
+        _ => {}
+    }
+
+    let name = non_empty(&rs.path).or(non_empty(&rs.url)).unwrap_or("");
+    let name = name.split(['?', '#']).next().unwrap_or("");
+    if name.to_ascii_lowercase().ends_with(".json") {
+        FORMAT_SOURCE
+    } else {
+        FORMAT_BINARY
+    }
+}
+
+fn cache_path(data_dir: &Path, rs: &RuleSetRef) -> PathBuf {
+    let ext = if format_of(rs) == FORMAT_SOURCE { "json" } else { "srs" };
+    data_dir.join(CACHE_DIR).join(format!("{}.{}", rs.tag, ext))
+}
+
+/// Рядом с копией хранится URL, из которого она скачана: после смены URL под тем же тегом
+/// старая копия не используется
+fn source_url_path(cache: &Path) -> PathBuf {
+    cache.with_extension("url")
+}
+
+fn cached_from(cache: &Path, url: &str) -> bool {
+    fs::read_to_string(source_url_path(cache)).is_ok_and(|u| u.trim() == url)
+}
+
+/// Файл для sing-box: локальный путь или скачанная копия. None — файла (пока) нет
+pub fn resolve(data_dir: &Path, rs: &RuleSetRef) -> Option<PathBuf> {
+    let path = match (non_empty(&rs.path), non_empty(&rs.url)) {
+        (Some(p), _) => PathBuf::from(p),
+        (None, Some(url)) => {
+            let cache = cache_path(data_dir, rs);
+            if !cached_from(&cache, url) {
+                return None;
+            }
+            cache
+        }
+        (None, None) => return None,
+    };
+    path.is_file().then_some(path)
+}
+
+fn validate_one(rs: &RuleSetRef) -> Result<(), String> {
+    let tag_ok = !rs.tag.is_empty()
+        && rs
+            .tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !tag_ok {
+        return Err("тег может содержать только латиницу, цифры, «-», «_» и «.»".to_string());
+    }
+
+    match (non_empty(&rs.path), non_empty(&rs.url)) {
+        (Some(_), Some(_)) | (None, None) => {
+            return Err("нужно указать либо путь к файлу, либо URL".to_string());
+        }
+        (Some(p), None) => {
+            if !Path::new(p).is_file() {
+                return Err(format!("файл не найден: {}", p));
+            }
+        }
+        (None, Some(u)) => {
+            if !u.starts_with("https://") && !u.starts_with("http://") {
+                return Err("URL должен начинаться с http:// или https://".to_string());
+            }
+        }
+    }
+
+    if let Some(f) = rs.format.as_deref() {
+        if f != FORMAT_BINARY && f != FORMAT_SOURCE {
+            return Err(format!("формат должен быть {} или {}", FORMAT_BINARY, FORMAT_SOURCE));
+        }
+    }
+    if rs.update_interval_hours == 0 {
+        return Err("интервал обновления должен быть не меньше часа".to_string());
+    }
+    Ok(())
+}
+
+/// Проверка при сохранении: корректные источники и уникальные теги во всех списках
+pub fn validate(split: &SplitRoutingSettings) -> Result<(), String> {
+    let mut tags: Vec<&str> = vec![];
+    let mut errors = vec![];
+
+    for rs in split.rule_set_refs() {
+        if let Err(e) = validate_one(rs) {
+            errors.push(format!("«{}»: {}", rs.tag, e));
+        } else if tags.contains(&rs.tag.as_str()) {
+            errors.push(format!("«{}»: тег уже используется", rs.tag));
+        }
+        tags.push(&rs.tag);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+fn is_stale(path: &Path, hours: u32) -> bool {
+    let age = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok());
+    match age {
+        Some(age) => age >= Duration::from_secs(u64::from(hours) * 3600),
+        None => true,
+    }
+}
+
+/// Проверка содержимого, чтобы страница ошибки или заглушка не заменила рабочую копию
+fn check_content(bytes: &[u8], format: &str) -> Result<(), String> {
+    if format == FORMAT_BINARY {
+        if !bytes.starts_with(SRS_MAGIC) {
+            return Err("файл не похож на бинарный rule-set (.srs)".to_string());
+        }
+        return Ok(());
+    }
+
+    let json: serde_json::Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("некорректный JSON: {}", e))?;
+    if !json.get("rules").is_some_and(|r| r.is_array()) {
+        return Err("в JSON нет массива rules".to_string());
+    }
+    Ok(())
+}
+
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    format: &str,
+    dest: &Path,
+) -> Result<(), String> {
+    let bytes = client
+        .get(url)
+        .header(reqwest::header::USER_AGENT, "ultunnel-desktop")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())?;
+    check_content(&bytes, format)?;
+
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    // через временный файл, чтобы не оставить обрезанную копию
+    let tmp = dest.with_extension("download");
+    fs::write(&tmp, &bytes).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, dest).map_err(|e| e.to_string())?;
+    fs::write(source_url_path(dest), url).map_err(|e| e.to_string())
+}
+
+/// Скачивает rule-set'ы без копии; вызывается перед запуском ядра, чтобы подключение
+/// не ждало обновления уже скачанных файлов
+pub async fn fetch_missing(data_dir: &Path, split: &SplitRoutingSettings) -> bool {
+    sync(data_dir, split, false).await
+}
+
+/// Скачивает отсутствующие и устаревшие удалённые rule-set'ы (фоновое обновление).
+/// Возвращает true, если какой-то файл обновился
+pub async fn refresh(data_dir: &Path, split: &SplitRoutingSettings) -> bool {
+    sync(data_dir, split, true).await
+}
+
+/// Ошибка загрузки не мешает запуску: остаётся прежняя копия, а rule-set без копии
+/// не попадёт в конфиг
+async fn sync(data_dir: &Path, split: &SplitRoutingSettings, update_stale: bool) -> bool {
+    if !split.enabled {
+        return false;
+    }
+    // при активном kill switch трафик мимо туннеля запрещён, загрузка всё равно не пройдёт
+    #[cfg(target_os = "linux")]
+    if crate::kill_switch::is_active() {
+        info!("Kill switch активен, rule-set'ы не скачиваются");
+        return false;
+    }
+
+    let client = match reqwest::Client::builder().timeout(DOWNLOAD_TIMEOUT).build() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Не удалось создать HTTP-клиент для rule-set'ов: {}", e);
+            return false;
+        }
+    };
+
+    let mut updated = false;
+    for rs in split.rule_set_refs() {
+        let (None, Some(url)) = (non_empty(&rs.path), non_empty(&rs.url)) else {
+            continue;
+        };
+        let dest = cache_path(data_dir, rs);
+        if cached_from(&dest, url) && !(update_stale && is_stale(&dest, rs.update_interval_hours)) {
+            continue;
+        }
+
+        match download(&client, url, format_of(rs), &dest).await {
+            Ok(()) => {
+                info!("Rule-set {} обновлён из {}", rs.tag, url);
+                updated = true;
+            }
+            Err(e) => warn!("Не удалось скачать rule-set {} ({}): {}", rs.tag, url, e),
+        }
+    }
+    updated
+}
+
+/// Периодическое обновление rule-set'ов; если обновился файл, запущенное ядро перезапускается
+pub fn spawn_rule_set_updater(app: AppHandle, state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+
+            let split = state.settings.lock().unwrap().active_split_routing();
+            if !refresh(&state.data_dir, &split).await {
+                continue;
+            }
+            if let Err(e) = crate::apply_routing_change(app.clone(), &state).await {
+                error!("Не удалось применить обновлённые rule-set'ы: {}", e);
+            }
+        }
+    });
+}
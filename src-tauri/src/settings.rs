@@ -1,11 +1,12 @@
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
 /// Раздельная маршрутизация (split tunneling).
 /// Важно: фактическая поддержка `process_name` зависит от платформы/ядра sing-box.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SplitRoutingSettings {
     #[serde(default)]
@@ -21,11 +22,273 @@ pub struct SplitRoutingSettings {
     #[serde(default)]
     pub proxy_domains: Vec<String>,
 
+    // IPv4/IPv6-подсети (CIDR)
+    #[serde(default)]
+    pub bypass_ips: Vec<String>,
+    #[serde(default)]
+    pub proxy_ips: Vec<String>,
+
+    // порты и диапазоны портов назначения: "443", "8000-9000"
+    #[serde(default)]
+    pub bypass_ports: Vec<String>,
+    #[serde(default)]
+    pub proxy_ports: Vec<String>,
+
+    // rule-set'ы sing-box (geosite/geoip, свои .srs/.json)
+    #[serde(default)]
+    pub bypass_rule_sets: Vec<RuleSetRef>,
+    #[serde(default)]
+    pub proxy_rule_sets: Vec<RuleSetRef>,
+
+    // блокировка (реклама, телеметрия): соединение отклоняется действием reject
+    #[serde(default)]
+    pub block_domains: Vec<String>,
+    #[serde(default)]
+    pub block_ips: Vec<String>,
+    #[serde(default)]
+    pub block_rule_sets: Vec<RuleSetRef>,
+
+    // порядок проверки: встроенные списки выше и собственные правила; первое совпадение побеждает
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+
     // теги outbound-ов в конфиге sing-box
     #[serde(default = "default_proxy_outbound")]
     pub proxy_outbound: String,
     #[serde(default = "default_direct_outbound")]
     pub direct_outbound: String,
+    // route.final; None — direct_outbound
+    #[serde(default)]
+    pub final_outbound: Option<String>,
+}
+
+/// Переопределения раздельной маршрутизации для профиля, накладываются на общие настройки.
+/// Дополнительные записи профиля важнее общих: домен из proxy_domains профиля
+/// убирается из общего bypass_domains и наоборот
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileRoutingOverride {
+    // None — как в общих настройках
+    #[serde(default)]
+    pub enabled: Option<bool>,
+
+    #[serde(default)]
+    pub bypass_apps: Vec<String>,
+    #[serde(default)]
+    pub proxy_apps: Vec<String>,
+    #[serde(default)]
+    pub bypass_domains: Vec<String>,
+    #[serde(default)]
+    pub proxy_domains: Vec<String>,
+    #[serde(default)]
+    pub bypass_ips: Vec<String>,
+    #[serde(default)]
+    pub proxy_ips: Vec<String>,
+
+    #[serde(default)]
+    pub final_outbound: Option<String>,
+}
+
+/// Записи профиля в список назначения; из противоположного списка они убираются
+fn merge_entries(target: &mut Vec<String>, opposite: &mut Vec<String>, extra: &[String]) {
+    for entry in extra {
+        opposite.retain(|e| e != entry);
+        if !target.contains(entry) {
+            target.push(entry.clone());
+        }
+    }
+}
+
+/// Rule-set sing-box: локальный файл или URL. Скачанные файлы кэшируются в папке данных
+/// (rule-sets/<tag>.srs|json) и обновляются не чаще update_interval_hours
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleSetRef {
+    pub tag: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    // binary (.srs) или source (.json); None — по расширению
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default = "default_rule_set_update_hours")]
+    pub update_interval_hours: u32,
+}
+
+fn default_rule_set_update_hours() -> u32 { 24 }
+
+/// Встроенные списки раздельной маршрутизации; ALL — порядок по умолчанию
+/// (без блокировки — тот, в котором списки проверялись до настраиваемого порядка)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleList {
+    BlockDomains,
+    BlockIps,
+    BlockRuleSets,
+    BypassApps,
+    BypassDomains,
+    BypassIps,
+    BypassPorts,
+    BypassRuleSets,
+    ProxyApps,
+    ProxyDomains,
+    ProxyIps,
+    ProxyPorts,
+    ProxyRuleSets,
+}
+
+impl RuleList {
+    pub const ALL: [RuleList; 13] = [
+        RuleList::BlockDomains,
+        RuleList::BlockIps,
+        RuleList::BlockRuleSets,
+        RuleList::BypassApps,
+        RuleList::BypassDomains,
+        RuleList::BypassIps,
+        RuleList::BypassPorts,
+        RuleList::BypassRuleSets,
+        RuleList::ProxyApps,
+        RuleList::ProxyDomains,
+        RuleList::ProxyIps,
+        RuleList::ProxyPorts,
+        RuleList::ProxyRuleSets,
+    ];
+
+    pub fn action(self) -> RouteAction {
+        match self {
+            RuleList::BlockDomains | RuleList::BlockIps | RuleList::BlockRuleSets => {
+                RouteAction::Block
+            }
+            RuleList::BypassApps
+            | RuleList::BypassDomains
+            | RuleList::BypassIps
+            | RuleList::BypassPorts
+            | RuleList::BypassRuleSets => RouteAction::Direct,
+            RuleList::ProxyApps
+            | RuleList::ProxyDomains
+            | RuleList::ProxyIps
+            | RuleList::ProxyPorts
+            | RuleList::ProxyRuleSets => RouteAction::Proxy,
+        }
+    }
+}
+
+/// Куда направить трафик, совпавший с правилом
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteAction {
+    #[default]
+    Proxy,
+    Direct,
+    /// отклонить соединение
+    Block,
+    /// outbound из конфига профиля по тегу (CustomRule::outbound)
+    Outbound,
+}
+
+/// Собственное правило. Условия объединяются как в sing-box: домены и подсети — по ИЛИ,
+/// порты и приложения — по И с ними
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomRule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub apps: Vec<String>,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub ips: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub action: RouteAction,
+    #[serde(default)]
+    pub outbound: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RouteRuleKind {
+    /// встроенный список (его же правят UI-чипы и Browser API)
+    List { list: RuleList },
+    Custom(CustomRule),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteRule {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub kind: RouteRuleKind,
+}
+
+impl SplitRoutingSettings {
+    /// Все rule-set'ы из списков (теги у них общие)
+    pub fn rule_set_refs(&self) -> impl Iterator<Item = &RuleSetRef> {
+        self.block_rule_sets
+            .iter()
+            .chain(&self.bypass_rule_sets)
+            .chain(&self.proxy_rule_sets)
+    }
+
+    /// Полный порядок правил: повторы встроенных списков убираются, недостающие
+    /// дописываются в конец в прежнем порядке, а списки блокировки — в начало.
+    /// Старые настройки без rules так получают прежнее поведение
+    pub fn ordered_rules(&self) -> Vec<RouteRule> {
+        let mut seen: Vec<RuleList> = vec![];
+        let mut rules: Vec<RouteRule> = vec![];
+        for r in &self.rules {
+            if let RouteRuleKind::List { list } = r.kind {
+                if seen.contains(&list) {
+                    continue;
+                }
+                seen.push(list);
+            }
+            rules.push(r.clone());
+        }
+
+        let mut blocked = 0;
+        for list in RuleList::ALL {
+            if seen.contains(&list) {
+                continue;
+            }
+            let rule = RouteRule {
+                enabled: true,
+                kind: RouteRuleKind::List { list },
+            };
+            if list.action() == RouteAction::Block {
+                rules.insert(blocked, rule);
+                blocked += 1;
+            } else {
+                rules.push(rule);
+            }
+        }
+        rules
+    }
+
+    pub fn normalize_rule_order(&mut self) {
+        self.rules = self.ordered_rules();
+    }
+
+    pub fn apply_override(&mut self, o: &ProfileRoutingOverride) {
+        if let Some(enabled) = o.enabled {
+            self.enabled = enabled;
+        }
+
+        merge_entries(&mut self.bypass_apps, &mut self.proxy_apps, &o.bypass_apps);
+        merge_entries(&mut self.proxy_apps, &mut self.bypass_apps, &o.proxy_apps);
+        merge_entries(&mut self.bypass_domains, &mut self.proxy_domains, &o.bypass_domains);
+        merge_entries(&mut self.proxy_domains, &mut self.bypass_domains, &o.proxy_domains);
+        merge_entries(&mut self.bypass_ips, &mut self.proxy_ips, &o.bypass_ips);
+        merge_entries(&mut self.proxy_ips, &mut self.bypass_ips, &o.proxy_ips);
+
+        if let Some(tag) = o.final_outbound.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+            self.final_outbound = Some(tag.to_string());
+        }
+    }
 }
 
 fn default_proxy_outbound() -> String { "proxy".into() }
@@ -39,8 +302,116 @@ impl Default for SplitRoutingSettings {
             proxy_apps: vec![],
             bypass_domains: vec![],
             proxy_domains: vec![],
+            bypass_ips: vec![],
+            proxy_ips: vec![],
+            bypass_ports: vec![],
+            proxy_ports: vec![],
+            bypass_rule_sets: vec![],
+            proxy_rule_sets: vec![],
+            block_domains: vec![],
+            block_ips: vec![],
+            block_rule_sets: vec![],
+            rules: vec![],
             proxy_outbound: default_proxy_outbound(),
             direct_outbound: default_direct_outbound(),
+            final_outbound: None,
+        }
+    }
+}
+
+/// Лимит трафика (upload + download), `None` — без ограничения
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaLimit {
+    #[serde(default)]
+    pub daily_bytes: Option<u64>,
+    #[serde(default)]
+    pub monthly_bytes: Option<u64>,
+}
+
+/// Что делать при превышении лимита
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QuotaAction {
+    /// только предупредить
+    #[default]
+    Warn,
+    /// остановить sing-box
+    Stop,
+    /// переключиться на другой профиль (fallback_profile или первый, не превысивший лимит)
+    Switch,
+}
+
+/// Лимиты трафика: общий и по профилям
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaSettings {
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub global: QuotaLimit,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, QuotaLimit>,
+
+    // пороги предупреждений в процентах от лимита
+    #[serde(default = "default_warn_thresholds")]
+    pub warn_thresholds: Vec<u8>,
+
+    #[serde(default)]
+    pub action: QuotaAction,
+    #[serde(default)]
+    pub fallback_profile: Option<String>,
+}
+
+fn default_warn_thresholds() -> Vec<u8> { vec![80, 95] }
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            global: QuotaLimit::default(),
+            profiles: BTreeMap::new(),
+            warn_thresholds: default_warn_thresholds(),
+            action: QuotaAction::default(),
+            fallback_profile: None,
+        }
+    }
+}
+
+/// Браузерное расширение, которому пользователь выдал токен Browser API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserPairing {
+    pub id: String,
+    // origin расширения, например chrome-extension://<id>
+    pub origin: String,
+    #[serde(default)]
+    pub name: String,
+    pub token: String,
+    pub created_at: String,
+    // отозванные токены остаются в списке и больше не принимаются
+    #[serde(default)]
+    pub revoked_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserApiSettings {
+    // порт на 127.0.0.1; если занят, берётся свободный (см. browser-api.json)
+    #[serde(default = "default_browser_api_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub pairings: Vec<BrowserPairing>,
+}
+
+pub fn default_browser_api_port() -> u16 { 18491 }
+
+impl Default for BrowserApiSettings {
+    fn default() -> Self {
+        Self {
+            port: default_browser_api_port(),
+            pairings: vec![],
         }
     }
 }
@@ -67,8 +438,45 @@ pub struct LocalSettings {
 
 	#[serde(default)]
 	pub autostart_enabled: bool,
+
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+
+    // включает route.find_process, чтобы Clash API отдавал путь процесса для статистики по приложениям
+    #[serde(default)]
+    pub app_traffic_tracking: bool,
+
+    // kill switch (Linux): блокировать трафик мимо туннеля, пока connected_intent = true
+    #[serde(default)]
+    pub kill_switch_enabled: bool,
+    // пользователь нажал «подключить» и ещё не нажимал «отключить»
+    #[serde(default)]
+    pub connected_intent: bool,
+
+    // при запуске: всегда подключаться / восстановить подключение, если оно было при выходе
+    #[serde(default)]
+    pub connect_on_launch: bool,
+    #[serde(default)]
+    pub restore_previous_state: bool,
+    // неудачные автоподключения подряд (защита от crash loop)
+    #[serde(default)]
+    pub auto_connect_failures: u32,
+
+    // Linux: проверять туннель и перезапускать ядро при смене маршрута по умолчанию
+    #[serde(default = "default_true")]
+    pub reconnect_on_network_change: bool,
+
+    // сопряжённые браузерные расширения
+    #[serde(default)]
+    pub browser_api: BrowserApiSettings,
+
+    // переопределения раздельной маршрутизации по имени профиля
+    #[serde(default)]
+    pub profile_routing: BTreeMap<String, ProfileRoutingOverride>,
 }
 
+fn default_true() -> bool { true }
+
 impl Default for LocalSettings {
     fn default() -> Self {
         Self {
@@ -79,6 +487,16 @@ impl Default for LocalSettings {
             macos_process_tunnel_enabled: false,
             macos_tunneled_processes: vec![],
 			autostart_enabled: false,
+            quotas: QuotaSettings::default(),
+            app_traffic_tracking: false,
+            kill_switch_enabled: false,
+            connected_intent: false,
+            connect_on_launch: false,
+            restore_previous_state: false,
+            auto_connect_failures: 0,
+            reconnect_on_network_change: true,
+            browser_api: BrowserApiSettings::default(),
+            profile_routing: BTreeMap::new(),
         }
     }
 }
@@ -86,13 +504,43 @@ impl Default for LocalSettings {
 impl LocalSettings {
     pub fn load(path: &Path) -> Self {
         if let Ok(s) = fs::read_to_string(path) {
-            if let Ok(v) = serde_json::from_str::<Self>(&s) {
+            if let Ok(mut v) = serde_json::from_str::<Self>(&s) {
+                v.split_routing.normalize_rule_order();
                 return v;
             }
         }
         Self::default()
     }
 
+    /// Раздельная маршрутизация для профиля: общие настройки с его переопределениями
+    pub fn split_routing_for(&self, profile: Option<&str>) -> SplitRoutingSettings {
+        let mut split = self.split_routing.clone();
+        if let Some(o) = profile.and_then(|p| self.profile_routing.get(p)) {
+            split.apply_override(o);
+        }
+        split
+    }
+
+    /// Удаляет переопределения профилей, которых больше нет в списке, чтобы новый профиль
+    /// с тем же именем не унаследовал чужие правила. true — что-то удалено
+    pub fn retain_profile_routing(&mut self, profiles: &[String]) -> bool {
+        let before = self.profile_routing.len();
+        self.profile_routing.retain(|name, _| profiles.contains(name));
+        self.profile_routing.len() != before
+    }
+
+    /// Переопределения выбранного профиля, если они заданы
+    pub fn active_routing_override(&self) -> Option<&ProfileRoutingOverride> {
+        self.selected_config
+            .as_deref()
+            .and_then(|p| self.profile_routing.get(p))
+    }
+
+    /// Маршрутизация, с которой работает ядро: с переопределениями выбранного профиля
+    pub fn active_split_routing(&self) -> SplitRoutingSettings {
+        self.split_routing_for(self.selected_config.as_deref())
+    }
+
     pub fn save(&self, path: &Path) -> Result<(), String> {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).map_err(|e| e.to_string())?;
@@ -101,3 +549,122 @@ impl LocalSettings {
         fs::write(path, s).map_err(|e| e.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(list: RuleList) -> RouteRule {
+        RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::List { list },
+        }
+    }
+
+    fn custom(name: &str) -> RouteRule {
+        RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::Custom(CustomRule {
+                name: name.to_string(),
+                domains: vec!["example.com".to_string()],
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn lists(rules: &[RouteRule]) -> Vec<Option<RuleList>> {
+        rules
+            .iter()
+            .map(|r| match r.kind {
+                RouteRuleKind::List { list } => Some(list),
+                RouteRuleKind::Custom(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn legacy_settings_get_default_order() {
+        let json = r#"{
+            "enabled": true,
+            "bypassApps": ["firefox"],
+            "bypassDomains": ["example.com"],
+            "proxyDomains": ["youtube.com"],
+            "bypassIps": ["10.0.0.0/8"]
+        }"#;
+        let split: SplitRoutingSettings = serde_json::from_str(json).unwrap();
+        assert!(split.rules.is_empty());
+
+        let rules = split.ordered_rules();
+        assert!(rules.iter().all(|r| r.enabled));
+        let expected: Vec<Option<RuleList>> = RuleList::ALL.iter().copied().map(Some).collect();
+        assert_eq!(lists(&rules), expected);
+    }
+
+    #[test]
+    fn duplicate_lists_are_dropped() {
+        let split = SplitRoutingSettings {
+            rules: vec![
+                list(RuleList::ProxyDomains),
+                custom("a"),
+                list(RuleList::ProxyDomains),
+                list(RuleList::BypassDomains),
+                custom("b"),
+            ],
+            ..Default::default()
+        };
+
+        let rules = split.ordered_rules();
+        assert_eq!(rules.len(), RuleList::ALL.len() + 2);
+        let proxy_domains = lists(&rules)
+            .iter()
+            .filter(|l| **l == Some(RuleList::ProxyDomains))
+            .count();
+        assert_eq!(proxy_domains, 1);
+        // собственные правила не считаются повторами
+        assert_eq!(lists(&rules).iter().filter(|l| l.is_none()).count(), 2);
+    }
+
+    #[test]
+    fn missing_block_lists_go_first() {
+        let split = SplitRoutingSettings {
+            rules: vec![
+                list(RuleList::ProxyDomains),
+                list(RuleList::BlockIps),
+                custom("a"),
+            ],
+            ..Default::default()
+        };
+
+        let rules = split.ordered_rules();
+        assert_eq!(
+            lists(&rules)[..5],
+            [
+                Some(RuleList::BlockDomains),
+                Some(RuleList::BlockRuleSets),
+                Some(RuleList::ProxyDomains),
+                // список, который пользователь поставил сам, остаётся на своём месте
+                Some(RuleList::BlockIps),
+                None,
+            ]
+        );
+        // остальные дописываются в конец в порядке по умолчанию
+        assert_eq!(lists(&rules)[5], Some(RuleList::BypassApps));
+        assert_eq!(lists(&rules).last(), Some(&Some(RuleList::ProxyRuleSets)));
+    }
+
+    #[test]
+    fn disabled_rules_keep_their_place() {
+        let mut off = list(RuleList::BypassDomains);
+        off.enabled = false;
+        let mut split = SplitRoutingSettings {
+            rules: vec![off.clone()],
+            ..Default::default()
+        };
+
+        split.normalize_rule_order();
+        assert_eq!(split.rules.len(), RuleList::ALL.len());
+        assert_eq!(split.rules[3], off);
+        // повторная нормализация ничего не меняет
+        assert_eq!(split.ordered_rules(), split.rules);
+    }
+}
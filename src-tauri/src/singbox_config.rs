@@ -0,0 +1,987 @@
+// Преобразование конфига профиля в singbox.json, общее для приложения и CLI
+
+use crate::clash_api;
+use crate::domains;
+use crate::net_rules;
+use crate::rule_sets;
+use crate::settings::RuleSetRef;
+use crate::settings::LocalSettings;
+use crate::settings::RouteAction;
+use crate::settings::RouteRuleKind;
+use crate::settings::RuleList;
+use crate::settings::SplitRoutingSettings;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Порт socks-in для браузерного прокси (расширение, PAC)
+pub const SOCKS5_INBOUND_PORT: u16 = 5613;
+
+fn normalize_primary_outbound_tag(cfg: &mut Value) {
+    let root = match cfg.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let outbounds = match root.get_mut("outbounds").and_then(|v| v.as_array_mut()) {
+        Some(v) => v,
+        None => return,
+    };
+
+    if outbounds.is_empty() {
+        return;
+    }
+
+    let first = match outbounds.get_mut(0).and_then(|v| v.as_object_mut()) {
+        Some(v) => v,
+        None => return,
+    };
+
+    let current_tag = first.get("tag").and_then(|v| v.as_str()).unwrap_or("");
+
+    if current_tag != "proxy" {
+        first.insert("tag".to_string(), Value::String("proxy".to_string()));
+    }
+
+    let outbound_type = first.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    if outbound_type == "wireguard" {
+        if first.get("server_port").is_none() {
+            first.insert("server_port".to_string(), Value::Number(51820.into()));
+        }
+    }
+}
+
+fn ensure_clash_api(cfg: &mut Value) {
+    let root = match cfg.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let experimental = root
+        .entry("experimental".to_string())
+        .or_insert_with(|| json!({}));
+
+    let experimental_obj = match experimental.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    experimental_obj.insert(
+        "clash_api".to_string(),
+        json!({
+            "external_controller": clash_api::CLASH_API_ADDR,
+            "secret": clash_api::CLASH_API_SECRET
+        }),
+    );
+}
+
+/// Итоговый singbox.json в папке данных приложения: конфиг профиля с учётом
+/// платформы, split routing (с переопределениями профиля), SOCKS5 inbound и Clash API
+pub fn write_singbox_config(
+    dir: &Path,
+    cfg: &Value,
+    settings: &LocalSettings,
+    profile: Option<&str>,
+) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let path: PathBuf = dir.join("singbox.json");
+    let v = build_singbox_config(dir, cfg, settings, profile);
+
+    let json = serde_json::to_string_pretty(&v).map_err(|e| e.to_string())?;
+    fs::write(&path, json).map_err(|e| e.to_string())?;
+
+    Ok(path)
+}
+
+/// Конфиг, который получит ядро, без записи на диск (для проверки маршрута)
+pub fn build_singbox_config(
+    dir: &Path,
+    cfg: &Value,
+    settings: &LocalSettings,
+    profile: Option<&str>,
+) -> Value {
+    let split = settings.split_routing_for(profile);
+    let mut v = cfg.clone();
+
+    normalize_primary_outbound_tag(&mut v);
+
+    #[cfg(target_os = "macos")]
+    {
+        patch_config_for_macos(&mut v);
+        patch_config_for_macos_process_rules(&mut v, settings);
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        patch_config_for_windows(&mut v, &split);
+    }
+
+    #[cfg(target_os = "linux")]
+    if settings.kill_switch_enabled {
+        crate::kill_switch::ensure_tun_interface_names(&mut v);
+    }
+
+    apply_split_routing(&mut v, &split, dir);
+    apply_socks5_inbound(&mut v, settings.socks5_inbound, &split.proxy_outbound);
+    if settings.app_traffic_tracking {
+        enable_find_process(&mut v);
+    }
+    ensure_clash_api(&mut v);
+    v
+}
+
+fn enable_find_process(cfg: &mut Value) {
+    let root = match cfg.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let route = root.entry("route").or_insert_with(|| json!({}));
+    if let Some(route_obj) = route.as_object_mut() {
+        route_obj.insert("find_process".to_string(), Value::Bool(true));
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn patch_config_for_windows(cfg: &mut serde_json::Value, split: &SplitRoutingSettings) {
+    /*    if !split.enabled {
+        return;
+    }*/
+
+    // 1) Для корректного определения процесса на Windows нужен stack=system
+    if let Some(inbounds) = cfg.get_mut("inbounds").and_then(|v| v.as_array_mut()) {
+        for ib in inbounds.iter_mut() {
+            if ib.get("type").and_then(|v| v.as_str()) == Some("tun") {
+                if let Some(obj) = ib.as_object_mut() {
+                    obj.insert(
+                        "stack".to_string(),
+                        serde_json::Value::String("system".to_string()),
+                    );
+                }
+            }
+        }
+    }
+
+    // 2) Избежать лупов
+    if let Some(root) = cfg.as_object_mut() {
+        let route = root.entry("route").or_insert_with(|| serde_json::json!({}));
+        if let Some(route_obj) = route.as_object_mut() {
+            route_obj
+                .entry("auto_detect_interface".to_string())
+                .or_insert(serde_json::Value::Bool(true));
+        }
+    }
+
+    if split.enabled {
+        if let Some(root) = cfg.as_object_mut() {
+            let route = root.entry("route").or_insert_with(|| serde_json::json!({}));
+            if let Some(route_obj) = route.as_object_mut() {
+                route_obj
+                    .entry("find_process".to_string())
+                    .or_insert(serde_json::Value::Bool(true));
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn patch_config_for_macos(cfg: &mut Value) {
+    if let Some(inbounds) = cfg.get_mut("inbounds").and_then(|v| v.as_array_mut()) {
+        for ib in inbounds.iter_mut() {
+            if ib.get("type").and_then(|v| v.as_str()) == Some("tun") {
+                if let Some(obj) = ib.as_object_mut() {
+                    obj.remove("interface_name");
+                }
+            }
+        }
+    }
+}
+
+fn split_process_tokens(list: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut names: Vec<String> = Vec::new();
+    let mut paths: Vec<String> = Vec::new();
+
+    for s in list {
+        let t = s.trim();
+        if t.is_empty() {
+            continue;
+        }
+
+        // Путь
+        if t.contains('/') || t.contains('\\') {
+            paths.push(t.to_string());
+
+            #[cfg(target_os = "windows")]
+            {
+                let low = t.to_ascii_lowercase();
+                if low != t {
+                    paths.push(low);
+                }
+            }
+
+            continue;
+        }
+
+        // Имя процесса
+        names.push(t.to_string());
+
+        #[cfg(target_os = "windows")]
+        {
+            let low = t.to_ascii_lowercase();
+            if low != t {
+                names.push(low.clone());
+            }
+
+            // добавить вариант с .exe и без .exe
+            if low.ends_with(".exe") {
+                let no = low.trim_end_matches(".exe").to_string();
+                if !no.is_empty() {
+                    names.push(no);
+                }
+            } else if !low.contains('.') {
+                names.push(format!("{low}.exe"));
+            }
+        }
+    }
+
+    (names, paths)
+}
+
+/// Условия по доменам: записи full:/suffix:/keyword:/regex: раскладываются по полям
+/// domain/domain_suffix/domain_keyword/domain_regex (в sing-box они объединяются по ИЛИ)
+fn add_domain_conditions(rule: &mut Map<String, Value>, entries: &[String]) {
+    for entry in entries {
+        let (kind, value) = domains::split_domain_rule(entry);
+        if value.is_empty() {
+            continue;
+        }
+        if let Some(list) = rule
+            .entry(kind.rule_field())
+            .or_insert_with(|| Value::Array(vec![]))
+            .as_array_mut()
+        {
+            list.push(Value::String(value.to_string()));
+        }
+    }
+}
+
+fn add_ip_conditions(rule: &mut Map<String, Value>, entries: &[String]) {
+    let cidrs: Vec<String> = entries
+        .iter()
+        .filter_map(|e| net_rules::normalize_cidr(e).ok())
+        .collect();
+    if !cidrs.is_empty() {
+        rule.insert("ip_cidr".into(), json!(cidrs));
+    }
+}
+
+/// Отдельные порты — в port, диапазоны — в port_range ("8000:9000"); в одном правиле это ИЛИ
+fn add_port_conditions(rule: &mut Map<String, Value>, entries: &[String]) {
+    let mut ports: Vec<u16> = vec![];
+    let mut ranges: Vec<String> = vec![];
+    for (from, to) in entries.iter().filter_map(|e| net_rules::parse_port_rule(e).ok()) {
+        if from == to {
+            ports.push(from);
+        } else {
+            ranges.push(format!("{}:{}", from, to));
+        }
+    }
+
+    if !ports.is_empty() {
+        rule.insert("port".into(), json!(ports));
+    }
+    if !ranges.is_empty() {
+        rule.insert("port_range".into(), json!(ranges));
+    }
+}
+
+/// Локальные rule-set'ы в route.rule_set и условие по их тегам.
+/// Rule-set без файла (ещё не скачан, файл удалён) пропускается, чтобы ядро всё равно запустилось
+fn add_rule_set_conditions(
+    route: &mut Map<String, Value>,
+    rule: &mut Map<String, Value>,
+    dir: &Path,
+    sets: &[RuleSetRef],
+) {
+    let mut tags: Vec<String> = vec![];
+    for rs in sets {
+        let Some(path) = rule_sets::resolve(dir, rs) else {
+            warn!("Rule-set {} недоступен, правило пропущено", rs.tag);
+            continue;
+        };
+
+        let entry = route.entry("rule_set").or_insert_with(|| json!([]));
+        if !entry.is_array() {
+            *entry = json!([]);
+        }
+        if let Some(list) = entry.as_array_mut() {
+            // одноимённый rule-set из профиля заменяем своим
+            list.retain(|v| v.get("tag").and_then(|t| t.as_str()) != Some(rs.tag.as_str()));
+            list.push(json!({
+                "type": "local",
+                "tag": rs.tag,
+                "format": rule_sets::format_of(rs),
+                "path": path.to_string_lossy()
+            }));
+        }
+        tags.push(rs.tag.clone());
+    }
+
+    if !tags.is_empty() {
+        rule.insert("rule_set".into(), json!(tags));
+    }
+}
+
+/// process_name и process_path в sing-box объединяются по И, поэтому приложения
+/// по имени и по пути — отдельные правила с одинаковыми остальными условиями
+fn with_processes(conditions: Map<String, Value>, apps: &[String]) -> Vec<Map<String, Value>> {
+    if apps.iter().all(|a| a.trim().is_empty()) {
+        return vec![conditions];
+    }
+
+    let (names, paths) = split_process_tokens(apps);
+    let mut out = vec![];
+    for (field, values) in [("process_path", paths), ("process_name", names)] {
+        if values.is_empty() {
+            continue;
+        }
+        let mut rule = conditions.clone();
+        rule.insert(field.into(), json!(values));
+        out.push(rule);
+    }
+    out
+}
+
+/// Outbound или действие для правила; None — некуда направить (пустой тег outbound)
+fn rule_target(
+    split: &SplitRoutingSettings,
+    action: RouteAction,
+    outbound: Option<&str>,
+) -> Option<(&'static str, String)> {
+    match action {
+        RouteAction::Proxy => Some(("outbound", split.proxy_outbound.clone())),
+        RouteAction::Direct => Some(("outbound", split.direct_outbound.clone())),
+        RouteAction::Block => Some(("action", "reject".to_string())),
+        RouteAction::Outbound => outbound
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| ("outbound", t.to_string())),
+    }
+}
+
+/// Условия одной записи порядка правил (без inbound и outbound)
+fn rule_conditions(
+    route: &mut Map<String, Value>,
+    split: &SplitRoutingSettings,
+    dir: &Path,
+    kind: &RouteRuleKind,
+) -> Vec<Map<String, Value>> {
+    let mut rule = Map::new();
+    let apps: &[String] = match kind {
+        RouteRuleKind::List { list } => match list {
+            RuleList::BypassApps => &split.bypass_apps,
+            RuleList::ProxyApps => &split.proxy_apps,
+            RuleList::BypassDomains => {
+                add_domain_conditions(&mut rule, &split.bypass_domains);
+                &[]
+            }
+            RuleList::ProxyDomains => {
+                add_domain_conditions(&mut rule, &split.proxy_domains);
+                &[]
+            }
+            RuleList::BypassIps => {
+                add_ip_conditions(&mut rule, &split.bypass_ips);
+                &[]
+            }
+            RuleList::ProxyIps => {
+                add_ip_conditions(&mut rule, &split.proxy_ips);
+                &[]
+            }
+            RuleList::BypassPorts => {
+                add_port_conditions(&mut rule, &split.bypass_ports);
+                &[]
+            }
+            RuleList::ProxyPorts => {
+                add_port_conditions(&mut rule, &split.proxy_ports);
+                &[]
+            }
+            RuleList::BlockDomains => {
+                add_domain_conditions(&mut rule, &split.block_domains);
+                &[]
+            }
+            RuleList::BlockIps => {
+                add_ip_conditions(&mut rule, &split.block_ips);
+                &[]
+            }
+            RuleList::BlockRuleSets => {
+                add_rule_set_conditions(route, &mut rule, dir, &split.block_rule_sets);
+                &[]
+            }
+            RuleList::BypassRuleSets => {
+                add_rule_set_conditions(route, &mut rule, dir, &split.bypass_rule_sets);
+                &[]
+            }
+            RuleList::ProxyRuleSets => {
+                add_rule_set_conditions(route, &mut rule, dir, &split.proxy_rule_sets);
+                &[]
+            }
+        },
+        RouteRuleKind::Custom(custom) => {
+            add_domain_conditions(&mut rule, &custom.domains);
+            add_ip_conditions(&mut rule, &custom.ips);
+            add_port_conditions(&mut rule, &custom.ports);
+            &custom.apps
+        }
+    };
+
+    let has_apps = apps.iter().any(|a| !a.trim().is_empty());
+    if rule.is_empty() && !has_apps {
+        return vec![];
+    }
+    with_processes(rule, apps)
+}
+
+/// Теги outbound'ов конфига профиля
+fn outbound_tags(cfg: &Value) -> Vec<String> {
+    cfg.get("outbounds")
+        .and_then(|v| v.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|o| o.get("tag").and_then(|v| v.as_str()))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn apply_split_routing(cfg: &mut serde_json::Value, split: &SplitRoutingSettings, dir: &Path) {
+    if !split.enabled {
+        return;
+    }
+
+    // outbound, которого нет в профиле, sing-box не примет и не запустится
+    let tags = outbound_tags(cfg);
+
+    let root = match cfg.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    if !root.contains_key("route") || !root.get("route").unwrap().is_object() {
+        root.insert("route".into(), Value::Object(Map::new()));
+    }
+
+    let route = root
+        .get_mut("route")
+        .and_then(|v| v.as_object_mut())
+        .unwrap();
+
+    let final_outbound = match split.final_outbound.as_ref() {
+        Some(tag) if !tags.contains(tag) => {
+            warn!(
+                "Outbound {} для route.final нет в профиле, используется {}",
+                tag, split.direct_outbound
+            );
+            &split.direct_outbound
+        }
+        Some(tag) => tag,
+        None => &split.direct_outbound,
+    };
+    route.insert("final".to_string(), Value::String(final_outbound.clone()));
+
+    route.insert("auto_detect_interface".to_string(), Value::Bool(true));
+
+    let order = split.ordered_rules();
+
+    let has_process_rules = order.iter().filter(|r| r.enabled).any(|r| match &r.kind {
+        RouteRuleKind::List { list: RuleList::BypassApps } => {
+            split.bypass_apps.iter().any(|s| !s.trim().is_empty())
+        }
+        RouteRuleKind::List { list: RuleList::ProxyApps } => {
+            split.proxy_apps.iter().any(|s| !s.trim().is_empty())
+        }
+        RouteRuleKind::List { .. } => false,
+        RouteRuleKind::Custom(custom) => custom.apps.iter().any(|s| !s.trim().is_empty()),
+    });
+
+    if has_process_rules {
+        route.insert("find_process".to_string(), Value::Bool(true));
+    }
+
+    let mut rules: Vec<Value> = vec![
+        json!({ "inbound": ["tun-in"], "action": "sniff" }),
+        json!({ "protocol": ["dns"], "action": "hijack-dns" }),
+    ];
+
+    // правила в порядке пользователя: sing-box применяет первое совпавшее
+    for entry in order.iter().filter(|r| r.enabled) {
+        let target = match &entry.kind {
+            RouteRuleKind::List { list } => rule_target(split, list.action(), None),
+            RouteRuleKind::Custom(custom) => {
+                rule_target(split, custom.action, custom.outbound.as_deref())
+            }
+        };
+        let Some((key, target)) = target else {
+            continue;
+        };
+        if key == "outbound" && !tags.contains(&target) {
+            warn!("Outbound {} нет в профиле, правило пропущено", target);
+            continue;
+        }
+
+        for mut rule in rule_conditions(route, split, dir, &entry.kind) {
+            rule.insert("inbound".into(), json!(["tun-in"]));
+            rule.insert(key.into(), Value::String(target.clone()));
+            rules.push(Value::Object(rule));
+        }
+    }
+
+    route.insert("rules".to_string(), Value::Array(rules));
+}
+
+fn apply_socks5_inbound(cfg: &mut serde_json::Value, enabled: bool, proxy_outbound: &str) {
+    let root = match cfg.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    // --- inbounds ---
+    if !root.contains_key("inbounds") || !root.get("inbounds").unwrap().is_array() {
+        root.insert("inbounds".into(), Value::Array(vec![]));
+    }
+    let inbounds = root
+        .get_mut("inbounds")
+        .and_then(|v| v.as_array_mut())
+        .unwrap();
+
+    // удалить старый socks-in (если был)
+    inbounds.retain(|ib| ib.get("tag").and_then(|v| v.as_str()) != Some("socks-in"));
+
+    if enabled {
+        inbounds.push(json!({
+            "type": "socks",
+            "tag": "socks-in",
+            "listen": "127.0.0.1",
+            "listen_port": SOCKS5_INBOUND_PORT,
+        }));
+    }
+
+    // --- route.rules ---
+    if !root.contains_key("route") || !root.get("route").unwrap().is_object() {
+        root.insert("route".into(), Value::Object(Map::new()));
+    }
+    let route = root
+        .get_mut("route")
+        .and_then(|v| v.as_object_mut())
+        .unwrap();
+
+    if !route.contains_key("rules") || !route.get("rules").unwrap().is_array() {
+        route.insert("rules".into(), Value::Array(vec![]));
+    }
+    let rules = route
+        .get_mut("rules")
+        .and_then(|v| v.as_array_mut())
+        .unwrap();
+
+    // удалить старые правила, относящиеся к socks-in:
+    // 1) sniff action для socks-in
+    // 2) простое правило socks-in -> outbound
+    rules.retain(|r| {
+        let o = match r.as_object() {
+            Some(x) => x,
+            None => return true,
+        };
+
+        let inbound_is_socks = match o.get("inbound").and_then(|v| v.as_array()) {
+            Some(a) => a.iter().any(|x| x.as_str() == Some("socks-in")),
+            None => false,
+        };
+
+        if !inbound_is_socks {
+            return true;
+        }
+
+        let action = o.get("action").and_then(|v| v.as_str());
+        if action == Some("sniff") || action == Some("reject") {
+            return false;
+        }
+
+        let outbound = o.get("outbound").and_then(|v| v.as_str());
+        let has_action = o.contains_key("action");
+        let has_other_conditions = o.contains_key("process_name")
+            || o.contains_key("process_path")
+            || o.contains_key("domain_suffix")
+            || o.contains_key("domain")
+            || o.contains_key("domain_keyword")
+            || o.contains_key("domain_regex")
+            || o.contains_key("ip_cidr")
+            || o.contains_key("port")
+            || o.contains_key("port_range")
+            || o.contains_key("network")
+            || o.contains_key("protocol");
+
+        if !has_action && !has_other_conditions && outbound.is_some() {
+            return false;
+        }
+
+        true
+    });
+
+    if enabled {
+        // вставить sniff и outbound-правило как можно выше
+        // сразу после уже существующих sniff/hijack-dns правил
+        let mut insert_at = 0usize;
+        for (i, r) in rules.iter().enumerate() {
+            let action = r
+                .as_object()
+                .and_then(|o| o.get("action"))
+                .and_then(|a| a.as_str());
+            if action == Some("sniff") || action == Some("hijack-dns") {
+                insert_at = i + 1;
+            }
+        }
+
+        // блокировки раздельной маршрутизации действуют и для браузерного прокси:
+        // копии reject-правил tun-in ставятся перед общим правилом socks-in
+        let reject: Vec<Value> = rules
+            .iter()
+            .filter_map(|r| r.as_object())
+            .filter(|o| o.get("action").and_then(|v| v.as_str()) == Some("reject"))
+            .filter(|o| {
+                o.get("inbound")
+                    .and_then(|v| v.as_array())
+                    .is_some_and(|a| a.iter().any(|x| x.as_str() == Some("tun-in")))
+            })
+            .map(|o| {
+                let mut o = o.clone();
+                o.insert("inbound".into(), json!(["socks-in"]));
+                Value::Object(o)
+            })
+            .collect();
+
+        rules.insert(
+            insert_at,
+            json!({
+                "inbound": ["socks-in"],
+                "action": "sniff"
+            }),
+        );
+        insert_at += 1;
+
+        for rule in reject {
+            rules.insert(insert_at, rule);
+            insert_at += 1;
+        }
+
+        let out = if proxy_outbound.trim().is_empty() {
+            "proxy"
+        } else {
+            proxy_outbound
+        };
+
+        rules.insert(
+            insert_at,
+            json!({
+                "inbound": ["socks-in"],
+                "outbound": out
+            }),
+        );
+    }
+}
+
+fn collect_tun_inbound_tags_for_profile_check(cfg: &serde_json::Value) -> Vec<String> {
+    cfg.get("inbounds")
+        .and_then(|v| v.as_array())
+        .map(|inbounds| {
+            inbounds
+                .iter()
+                .filter(|ib| ib.get("type").and_then(|v| v.as_str()) == Some("tun"))
+                .filter_map(|ib| ib.get("tag").and_then(|v| v.as_str()))
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default()
+}
+
+fn configure_full_vpn_profile_check(cfg: &mut serde_json::Value, tun_tags: &[String]) {
+    let root = match cfg.as_object_mut() {
+        Some(v) => v,
+        None => return,
+    };
+
+    // Проверка профилей должна поднимать именно полноценный TUN/VPN,
+    // а не локальный proxy-inbound. Поэтому для временного конфига проверки
+    // отключаем split-routing/process-routing и делаем proxy финальным outbound
+    // для всего системного трафика, попадающего в TUN.
+    if !root.contains_key("route") || !root.get("route").unwrap().is_object() {
+        root.insert("route".into(), Value::Object(Map::new()));
+    }
+
+    let route = root
+        .get_mut("route")
+        .and_then(|v| v.as_object_mut())
+        .unwrap();
+
+    route.insert("final".to_string(), Value::String("proxy".to_string()));
+    route.insert("auto_detect_interface".to_string(), Value::Bool(true));
+    route.remove("find_process");
+
+    // Убираем правила, которые могли увести часть трафика напрямую по process_name,
+    // process_path, domain_suffix и т.п. Для проверки нужен чистый full-tunnel:
+    // DNS перехватывается, остальной трафик идет в route.final = proxy.
+    route.insert(
+        "rules".to_string(),
+        Value::Array(vec![
+            json!({ "inbound": tun_tags, "action": "sniff" }),
+            json!({ "protocol": ["dns"], "action": "hijack-dns" }),
+        ]),
+    );
+}
+
+fn ensure_tun_inbound_exists_for_profile_check(cfg: &serde_json::Value) -> Result<Vec<String>, String> {
+    let tun_tags = collect_tun_inbound_tags_for_profile_check(cfg);
+
+    if !tun_tags.is_empty() {
+        Ok(tun_tags)
+    } else {
+        Err("В конфиге нет TUN inbound, поэтому полноценный VPN для проверки поднять нельзя".to_string())
+    }
+}
+
+pub fn write_singbox_config_for_profile_check(
+    dir: &Path,
+    cfg: &Value,
+    settings: &LocalSettings,
+) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let path: PathBuf = dir.join("singbox-profile-check.json");
+    let mut v = cfg.clone();
+
+    normalize_primary_outbound_tag(&mut v);
+    let tun_tags = ensure_tun_inbound_exists_for_profile_check(&v)?;
+
+    #[cfg(target_os = "macos")]
+    {
+        let _ = settings;
+        patch_config_for_macos(&mut v);
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let mut full_tunnel_split = settings.split_routing.clone();
+        full_tunnel_split.enabled = false;
+        patch_config_for_windows(&mut v, &full_tunnel_split);
+    }
+
+    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
+    {
+        let _ = settings;
+    }
+
+    configure_full_vpn_profile_check(&mut v, &tun_tags);
+    ensure_clash_api(&mut v);
+
+    let json = serde_json::to_string_pretty(&v).map_err(|e| e.to_string())?;
+    fs::write(&path, json).map_err(|e| e.to_string())?;
+
+    Ok(path)
+}
+
+#[cfg(target_os = "macos")]
+fn patch_config_for_macos_process_rules(cfg: &mut Value, settings: &LocalSettings) {
+    if !settings.macos_process_tunnel_enabled {
+        return;
+    }
+
+    let processes = settings
+        .macos_tunneled_processes
+        .iter()
+        .filter(|s| !s.trim().is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    if processes.is_empty() {
+        return;
+    }
+    let route = cfg
+        .as_object_mut()
+        .and_then(|root| root.get_mut("route"))
+        .and_then(|v| v.as_object_mut());
+    if route.is_none() {
+        // если route отсутствует — создаем
+        if let Some(root) = cfg.as_object_mut() {
+            root.insert("route".to_string(), serde_json::json!({}));
+        }
+    }
+    let route = cfg
+        .get_mut("route")
+        .and_then(|v| v.as_object_mut())
+        .unwrap();
+    let rules = route
+        .entry("rules".to_string())
+        .or_insert_with(|| serde_json::json!([]));
+    let rules_arr = rules.as_array_mut().unwrap();
+    let rule = json!({
+        "process_name": processes,
+        "outbound": settings.split_routing.proxy_outbound
+    });
+    rules_arr.insert(0, rule);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::CustomRule;
+    use crate::settings::RouteRule;
+
+    fn profile() -> Value {
+        json!({
+            "outbounds": [
+                { "type": "vless", "tag": "proxy" },
+                { "type": "direct", "tag": "direct" },
+            ],
+        })
+    }
+
+    /// Правила раздельной маршрутизации для tun-in (без sniff и hijack-dns)
+    fn routed(split: SplitRoutingSettings) -> Vec<Value> {
+        let settings = LocalSettings {
+            split_routing: split,
+            ..Default::default()
+        };
+        let cfg = build_singbox_config(&std::env::temp_dir(), &profile(), &settings, None);
+        cfg["route"]["rules"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|r| r["inbound"] == json!(["tun-in"]) && r["action"] != "sniff")
+            .cloned()
+            .collect()
+    }
+
+    fn list(list: RuleList) -> RouteRule {
+        RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::List { list },
+        }
+    }
+
+    #[test]
+    fn legacy_lists_keep_previous_order() {
+        let json = r#"{
+            "enabled": true,
+            "bypassApps": ["firefox", "/usr/bin/curl"],
+            "bypassDomains": ["example.com"],
+            "proxyDomains": ["youtube.com"],
+            "bypassIps": ["10.0.0.0/8"],
+            "blockDomains": ["ads.example.com"]
+        }"#;
+        let split: SplitRoutingSettings = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            routed(split),
+            vec![
+                json!({ "inbound": ["tun-in"], "domain_suffix": ["ads.example.com"], "action": "reject" }),
+                json!({ "inbound": ["tun-in"], "process_path": ["/usr/bin/curl"], "outbound": "direct" }),
+                json!({ "inbound": ["tun-in"], "process_name": ["firefox"], "outbound": "direct" }),
+                json!({ "inbound": ["tun-in"], "domain_suffix": ["example.com"], "outbound": "direct" }),
+                json!({ "inbound": ["tun-in"], "ip_cidr": ["10.0.0.0/8"], "outbound": "direct" }),
+                json!({ "inbound": ["tun-in"], "domain_suffix": ["youtube.com"], "outbound": "proxy" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn user_order_without_duplicates_and_disabled_lists() {
+        let mut bypass_ips = list(RuleList::BypassIps);
+        bypass_ips.enabled = false;
+        let split = SplitRoutingSettings {
+            enabled: true,
+            proxy_domains: vec!["youtube.com".into()],
+            bypass_domains: vec!["example.com".into()],
+            bypass_ips: vec!["10.0.0.0/8".into()],
+            block_ips: vec!["192.0.2.0/24".into()],
+            rules: vec![
+                list(RuleList::ProxyDomains),
+                bypass_ips,
+                list(RuleList::BypassDomains),
+                list(RuleList::ProxyDomains),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            routed(split),
+            vec![
+                json!({ "inbound": ["tun-in"], "ip_cidr": ["192.0.2.0/24"], "action": "reject" }),
+                json!({ "inbound": ["tun-in"], "domain_suffix": ["youtube.com"], "outbound": "proxy" }),
+                json!({ "inbound": ["tun-in"], "domain_suffix": ["example.com"], "outbound": "direct" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_rule_apps_split_into_name_and_path_rules() {
+        let custom = RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::Custom(CustomRule {
+                name: "steam".into(),
+                apps: vec!["steam".into(), "/opt/steam/steam".into()],
+                ports: vec!["27015-27030".into()],
+                action: RouteAction::Outbound,
+                outbound: Some("proxy".into()),
+                ..Default::default()
+            }),
+        };
+        let split = SplitRoutingSettings {
+            enabled: true,
+            bypass_apps: vec!["steam".into()],
+            rules: vec![custom, list(RuleList::BypassApps)],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            routed(split),
+            vec![
+                json!({
+                    "inbound": ["tun-in"],
+                    "port_range": ["27015:27030"],
+                    "process_path": ["/opt/steam/steam"],
+                    "outbound": "proxy",
+                }),
+                json!({
+                    "inbound": ["tun-in"],
+                    "port_range": ["27015:27030"],
+                    "process_name": ["steam"],
+                    "outbound": "proxy",
+                }),
+                json!({ "inbound": ["tun-in"], "process_name": ["steam"], "outbound": "direct" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rule_with_unknown_outbound_is_skipped() {
+        let custom = RouteRule {
+            enabled: true,
+            kind: RouteRuleKind::Custom(CustomRule {
+                domains: vec!["example.com".into()],
+                action: RouteAction::Outbound,
+                outbound: Some("missing".into()),
+                ..Default::default()
+            }),
+        };
+        let split = SplitRoutingSettings {
+            enabled: true,
+            rules: vec![custom],
+            ..Default::default()
+        };
+
+        assert!(routed(split).is_empty());
+    }
+}
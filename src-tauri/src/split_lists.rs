@@ -0,0 +1,741 @@
+// Импорт и экспорт списков раздельной маршрутизации (домены и приложения) в форматах:
+// простой текст (запись на строку), правила Clash (DOMAIN-SUFFIX,example.com,PROXY),
+// rule-set sing-box в виде source JSON и собственный JSON со всеми списками сразу.
+// Текст и rule-set описывают один список, Clash и собственный JSON — несколько.
+
+use crate::domains;
+use crate::domains::DomainMatch;
+use crate::settings::SplitRoutingSettings;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Метка собственного формата в поле format
+const OWN_FORMAT: &str = "ultunnel-split-lists";
+const OWN_VERSION: u32 = 1;
+/// Версия source-формата rule-set'ов sing-box
+const RULE_SET_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ListFormat {
+    Text,
+    Clash,
+    RuleSet,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ListName {
+    ProxyDomains,
+    BypassDomains,
+    BlockDomains,
+    ProxyApps,
+    BypassApps,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportMode {
+    /// добавить к существующим записям
+    #[default]
+    Merge,
+    /// заменить списки, которые есть в файле
+    Replace,
+}
+
+/// Итог импорта: что добавлено, что пропущено и какие записи противоречат друг другу
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub added: usize,
+    pub duplicates: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub skipped: Vec<String>,
+    pub split: SplitRoutingSettings,
+    // списки сохранены, но запущенное ядро не удалось перезапустить с ними
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apply_error: Option<String>,
+}
+
+/// Собственный формат; отсутствующий список при импорте не трогается
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListsFile {
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy_domains: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bypass_domains: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    block_domains: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy_apps: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bypass_apps: Option<Vec<String>>,
+}
+
+impl ListName {
+    const ALL: [ListName; 5] = [
+        ListName::ProxyDomains,
+        ListName::BypassDomains,
+        ListName::BlockDomains,
+        ListName::ProxyApps,
+        ListName::BypassApps,
+    ];
+
+    fn is_apps(self) -> bool {
+        matches!(self, ListName::ProxyApps | ListName::BypassApps)
+    }
+
+    fn get(self, split: &SplitRoutingSettings) -> &Vec<String> {
+        match self {
+            ListName::ProxyDomains => &split.proxy_domains,
+            ListName::BypassDomains => &split.bypass_domains,
+            ListName::BlockDomains => &split.block_domains,
+            ListName::ProxyApps => &split.proxy_apps,
+            ListName::BypassApps => &split.bypass_apps,
+        }
+    }
+
+    fn get_mut(self, split: &mut SplitRoutingSettings) -> &mut Vec<String> {
+        match self {
+            ListName::ProxyDomains => &mut split.proxy_domains,
+            ListName::BypassDomains => &mut split.bypass_domains,
+            ListName::BlockDomains => &mut split.block_domains,
+            ListName::ProxyApps => &mut split.proxy_apps,
+            ListName::BypassApps => &mut split.bypass_apps,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ListName::ProxyDomains | ListName::ProxyApps => "через прокси",
+            ListName::BypassDomains | ListName::BypassApps => "напрямую",
+            ListName::BlockDomains => "блокировка",
+        }
+    }
+
+    fn clash_policy(self) -> &'static str {
+        match self {
+            ListName::ProxyDomains | ListName::ProxyApps => "PROXY",
+            ListName::BypassDomains | ListName::BypassApps => "DIRECT",
+            ListName::BlockDomains => "REJECT",
+        }
+    }
+
+    /// Список того же назначения для доменов или приложений
+    fn with_kind(self, apps: bool) -> Option<ListName> {
+        match (self, apps) {
+            (ListName::ProxyDomains | ListName::ProxyApps, false) => Some(ListName::ProxyDomains),
+            (ListName::ProxyDomains | ListName::ProxyApps, true) => Some(ListName::ProxyApps),
+            (ListName::BypassDomains | ListName::BypassApps, false) => {
+                Some(ListName::BypassDomains)
+            }
+            (ListName::BypassDomains | ListName::BypassApps, true) => Some(ListName::BypassApps),
+            (ListName::BlockDomains, false) => Some(ListName::BlockDomains),
+            // блокировки по приложению нет
+            (ListName::BlockDomains, true) => None,
+        }
+    }
+}
+
+/// Разобранный файл: записи по спискам в порядке появления
+#[derive(Default)]
+struct Parsed {
+    lists: Vec<(ListName, Vec<String>)>,
+    skipped: Vec<String>,
+}
+
+impl Parsed {
+    fn touch(&mut self, list: ListName) -> &mut Vec<String> {
+        let idx = match self.lists.iter().position(|(l, _)| *l == list) {
+            Some(i) => i,
+            None => {
+                self.lists.push((list, vec![]));
+                self.lists.len() - 1
+            }
+        };
+        &mut self.lists[idx].1
+    }
+
+    /// Запись после проверки; некорректная попадает в skipped
+    fn push(&mut self, list: ListName, entry: &str, source: &str) {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            return;
+        }
+        let value = if list.is_apps() {
+            Ok(entry.to_string())
+        } else {
+            domains::normalize_domain_rule(entry)
+        };
+        match value {
+            Ok(v) => self.touch(list).push(v),
+            Err(e) => self.skipped.push(format!("{}: {}", source, e)),
+        }
+    }
+}
+
+fn required_list(list: Option<ListName>) -> Result<ListName, String> {
+    list.ok_or("Для этого формата нужно выбрать список".to_string())
+}
+
+fn parse_text(content: &str, list: ListName) -> Parsed {
+    let mut parsed = Parsed::default();
+    parsed.touch(list);
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with('!')
+            || line.starts_with("//")
+        {
+            continue;
+        }
+        // строки hosts-файлов: "0.0.0.0 ads.example.com"
+        let mut tokens = line.split_whitespace();
+        let entry = match (tokens.next(), tokens.next()) {
+            (Some(ip), Some(host)) if !list.is_apps() && ip.parse::<std::net::IpAddr>().is_ok() => {
+                host
+            }
+            _ => line,
+        };
+        parsed.push(list, entry, &format!("строка {}", n + 1));
+    }
+    parsed
+}
+
+fn parse_clash(content: &str, list: Option<ListName>) -> Parsed {
+    let mut parsed = Parsed::default();
+    if let Some(list) = list {
+        parsed.touch(list);
+    }
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.ends_with(':') {
+            continue;
+        }
+        let line = line
+            .trim_start_matches('-')
+            .trim()
+            .trim_matches(['\'', '"']);
+        let source = format!("строка {}", n + 1);
+
+        let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (kind, value) = match parts.as_slice() {
+            [kind, value, ..] => (kind.to_ascii_uppercase(), *value),
+            _ => {
+                parsed.skipped.push(format!("{}: не правило Clash", source));
+                continue;
+            }
+        };
+        let entry = match kind.as_str() {
+            "DOMAIN" => DomainMatch::Full.entry(value),
+            "DOMAIN-SUFFIX" => DomainMatch::Suffix.entry(value.trim_start_matches('.')),
+            "DOMAIN-KEYWORD" => DomainMatch::Keyword.entry(value),
+            "DOMAIN-REGEX" => DomainMatch::Regex.entry(value),
+            "PROCESS-NAME" | "PROCESS-PATH" => value.to_string(),
+            other => {
+                parsed
+                    .skipped
+                    .push(format!("{}: тип {} не поддерживается", source, other));
+                continue;
+            }
+        };
+        let apps = kind.starts_with("PROCESS-");
+
+        // без политики (payload rule-provider'а) — в выбранный список, иначе по политике
+        let target = match parts.get(2).map(|p| p.to_ascii_uppercase()) {
+            None => list.unwrap_or(ListName::ProxyDomains).with_kind(apps),
+            Some(p) if p == "DIRECT" => ListName::BypassDomains.with_kind(apps),
+            Some(p) if p.starts_with("REJECT") => ListName::BlockDomains.with_kind(apps),
+            Some(_) => ListName::ProxyDomains.with_kind(apps),
+        };
+        match target {
+            Some(target) => parsed.push(target, &entry, &source),
+            None => parsed.skipped.push(format!(
+                "{}: блокировка приложений не поддерживается",
+                source
+            )),
+        }
+    }
+    parsed
+}
+
+/// domain_suffix с точкой в начале (".example.com") в sing-box совпадает только
+/// с поддоменами, а suffix: — ещё и с самим доменом, поэтому такая запись становится regex:
+fn suffix_entry(value: &str) -> String {
+    match value.trim().strip_prefix('.') {
+        Some(rest) => DomainMatch::Regex.entry(&format!(r"\.{}$", regex::escape(rest))),
+        None => DomainMatch::Suffix.entry(value),
+    }
+}
+
+fn parse_rule_set(content: &str, list: ListName) -> Result<Parsed, String> {
+    let v: Value =
+        serde_json::from_str(content).map_err(|e| format!("Некорректный JSON: {}", e))?;
+    let rules = v
+        .get("rules")
+        .and_then(|r| r.as_array())
+        .ok_or("В rule-set нет массива rules")?;
+
+    let mut parsed = Parsed::default();
+    parsed.touch(list);
+
+    for (n, rule) in rules.iter().enumerate() {
+        let Some(rule) = rule.as_object() else {
+            continue;
+        };
+        for (field, values) in rule {
+            let kind = match field.as_str() {
+                "domain" => Some(DomainMatch::Full),
+                "domain_suffix" => Some(DomainMatch::Suffix),
+                "domain_keyword" => Some(DomainMatch::Keyword),
+                "domain_regex" => Some(DomainMatch::Regex),
+                "process_name" | "process_path" => None,
+                other => {
+                    parsed.skipped.push(format!(
+                        "правило {}: поле {} не поддерживается",
+                        n + 1,
+                        other
+                    ));
+                    continue;
+                }
+            };
+            if kind.is_some() == list.is_apps() {
+                parsed.skipped.push(format!(
+                    "правило {}: поле {} не подходит для выбранного списка",
+                    n + 1,
+                    field
+                ));
+                continue;
+            }
+
+            let values: Vec<&str> = match values {
+                Value::String(s) => vec![s.as_str()],
+                Value::Array(items) => items.iter().filter_map(|v| v.as_str()).collect(),
+                _ => vec![],
+            };
+            let source = format!("правило {}", n + 1);
+            for value in values {
+                let entry = match kind {
+                    Some(DomainMatch::Suffix) => suffix_entry(value),
+                    Some(kind) => kind.entry(value),
+                    None => value.to_string(),
+                };
+                parsed.push(list, &entry, &source);
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_json(content: &str) -> Result<Parsed, String> {
+    let file: ListsFile =
+        serde_json::from_str(content).map_err(|e| format!("Некорректный JSON: {}", e))?;
+    if let Some(format) = file.format.as_deref() {
+        if format != OWN_FORMAT {
+            return Err(format!("Неизвестный формат файла: {}", format));
+        }
+    }
+
+    let mut parsed = Parsed::default();
+    let lists = [
+        (ListName::ProxyDomains, file.proxy_domains),
+        (ListName::BypassDomains, file.bypass_domains),
+        (ListName::BlockDomains, file.block_domains),
+        (ListName::ProxyApps, file.proxy_apps),
+        (ListName::BypassApps, file.bypass_apps),
+    ];
+    for (list, entries) in lists {
+        let Some(entries) = entries else {
+            continue;
+        };
+        parsed.touch(list);
+        for entry in entries {
+            parsed.push(list, &entry, &format!("«{}»", entry));
+        }
+    }
+    Ok(parsed)
+}
+
+/// Записи, которые стоят сразу в нескольких списках с разным назначением
+fn conflicts(split: &SplitRoutingSettings) -> Vec<String> {
+    let groups = [
+        [
+            ListName::ProxyDomains,
+            ListName::BypassDomains,
+            ListName::BlockDomains,
+        ]
+        .as_slice(),
+        [ListName::ProxyApps, ListName::BypassApps].as_slice(),
+    ];
+
+    let mut out = vec![];
+    for group in groups {
+        // запись -> списки, в которых она встречается (в порядке первого появления)
+        let mut order: Vec<&String> = vec![];
+        let mut found: HashMap<&String, Vec<&str>> = HashMap::new();
+        for list in group {
+            for entry in list.get(split) {
+                let labels = found.entry(entry).or_insert_with(|| {
+                    order.push(entry);
+                    vec![]
+                });
+                if !labels.contains(&list.label()) {
+                    labels.push(list.label());
+                }
+            }
+        }
+        for entry in order {
+            let labels = &found[entry];
+            if labels.len() > 1 {
+                out.push(format!("{}: {}", entry, labels.join(" и ")));
+            }
+        }
+    }
+    out
+}
+
+pub fn import(
+    split: &mut SplitRoutingSettings,
+    content: &str,
+    format: ListFormat,
+    mode: ImportMode,
+    list: Option<ListName>,
+) -> Result<ImportReport, String> {
+    let parsed = match format {
+        ListFormat::Text => parse_text(content, required_list(list)?),
+        ListFormat::Clash => parse_clash(content, list),
+        ListFormat::RuleSet => parse_rule_set(content, required_list(list)?)?,
+        ListFormat::Json => parse_json(content)?,
+    };
+
+    let mut added = 0;
+    let mut duplicates = vec![];
+    for (list, entries) in parsed.lists {
+        let target = list.get_mut(split);
+        if mode == ImportMode::Replace {
+            target.clear();
+        }
+        let mut present: HashSet<String> = target.iter().cloned().collect();
+        for entry in entries {
+            if present.contains(&entry) {
+                duplicates.push(entry);
+            } else {
+                present.insert(entry.clone());
+                target.push(entry);
+                added += 1;
+            }
+        }
+    }
+
+    Ok(ImportReport {
+        added,
+        duplicates,
+        conflicts: conflicts(split),
+        skipped: parsed.skipped,
+        split: split.clone(),
+        apply_error: None,
+    })
+}
+
+fn export_clash(split: &SplitRoutingSettings, lists: &[ListName]) -> String {
+    let mut out = String::from("rules:\n");
+    for list in lists {
+        for entry in list.get(split) {
+            let (kind, value) = if list.is_apps() {
+                let kind = if entry.contains('/') || entry.contains('\\') {
+                    "PROCESS-PATH"
+                } else {
+                    "PROCESS-NAME"
+                };
+                (kind, entry.as_str())
+            } else {
+                match domains::split_domain_rule(entry) {
+                    (DomainMatch::Full, v) => ("DOMAIN", v),
+                    (DomainMatch::Suffix, v) => ("DOMAIN-SUFFIX", v),
+                    (DomainMatch::Keyword, v) => ("DOMAIN-KEYWORD", v),
+                    (DomainMatch::Regex, v) => ("DOMAIN-REGEX", v),
+                }
+            };
+            out.push_str(&format!("  - {},{},{}\n", kind, value, list.clash_policy()));
+        }
+    }
+    out
+}
+
+fn export_rule_set(split: &SplitRoutingSettings, list: ListName) -> Result<String, String> {
+    let mut rule = Map::new();
+    for entry in list.get(split) {
+        let (field, value) = if list.is_apps() {
+            let field = if entry.contains('/') || entry.contains('\\') {
+                "process_path"
+            } else {
+                "process_name"
+            };
+            (field, entry.as_str())
+        } else {
+            let (kind, value) = domains::split_domain_rule(entry);
+            (kind.rule_field(), value)
+        };
+        if let Some(values) = rule
+            .entry(field)
+            .or_insert_with(|| Value::Array(vec![]))
+            .as_array_mut()
+        {
+            values.push(Value::String(value.to_string()));
+        }
+    }
+
+    let rules = if rule.is_empty() {
+        vec![]
+    } else {
+        vec![Value::Object(rule)]
+    };
+    serde_json::to_string_pretty(&json!({ "version": RULE_SET_VERSION, "rules": rules }))
+        .map_err(|e| e.to_string())
+}
+
+pub fn export(
+    split: &SplitRoutingSettings,
+    format: ListFormat,
+    list: Option<ListName>,
+) -> Result<String, String> {
+    match format {
+        ListFormat::Text => {
+            let entries = required_list(list)?.get(split);
+            Ok(entries.iter().map(|e| format!("{}\n", e)).collect())
+        }
+        ListFormat::Clash => match list {
+            Some(list) => Ok(export_clash(split, &[list])),
+            None => Ok(export_clash(split, &ListName::ALL)),
+        },
+        ListFormat::RuleSet => export_rule_set(split, required_list(list)?),
+        ListFormat::Json => {
+            let file = ListsFile {
+                format: Some(OWN_FORMAT.to_string()),
+                version: Some(OWN_VERSION),
+                proxy_domains: Some(split.proxy_domains.clone()),
+                bypass_domains: Some(split.bypass_domains.clone()),
+                block_domains: Some(split.block_domains.clone()),
+                proxy_apps: Some(split.proxy_apps.clone()),
+                bypass_apps: Some(split.bypass_apps.clone()),
+            };
+            serde_json::to_string_pretty(&file).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn sample() -> SplitRoutingSettings {
+        SplitRoutingSettings {
+            proxy_domains: strings(&[
+                "youtube.com",
+                "full:www.example.org",
+                "keyword:tracker",
+                r"regex:^ads\d+\.example\.com$",
+            ]),
+            bypass_domains: strings(&["example.ru"]),
+            block_domains: strings(&["ads.example.com"]),
+            proxy_apps: strings(&["telegram", "/opt/app/bin/app"]),
+            bypass_apps: strings(&["firefox"]),
+            ..Default::default()
+        }
+    }
+
+    /// Экспорт sample() и импорт в пустые настройки
+    fn round_trip(format: ListFormat, list: Option<ListName>) -> SplitRoutingSettings {
+        let content = export(&sample(), format, list).unwrap();
+        let mut split = SplitRoutingSettings::default();
+        let report = import(&mut split, &content, format, ImportMode::Replace, list).unwrap();
+        assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+        assert!(report.duplicates.is_empty(), "{:?}", report.duplicates);
+        split
+    }
+
+    fn sorted(list: &[String]) -> Vec<String> {
+        let mut list = list.to_vec();
+        list.sort();
+        list
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let src = sample();
+        for list in ListName::ALL {
+            let split = round_trip(ListFormat::Text, Some(list));
+            assert_eq!(list.get(&split), list.get(&src), "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn rule_set_round_trip() {
+        let src = sample();
+        for list in ListName::ALL {
+            let split = round_trip(ListFormat::RuleSet, Some(list));
+            // в rule-set записи сгруппированы по полям, порядок между полями не сохраняется
+            assert_eq!(
+                sorted(list.get(&split)),
+                sorted(list.get(&src)),
+                "{:?}",
+                list
+            );
+        }
+    }
+
+    #[test]
+    fn clash_round_trip() {
+        let src = sample();
+        let split = round_trip(ListFormat::Clash, None);
+        for list in ListName::ALL {
+            assert_eq!(list.get(&split), list.get(&src), "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let src = sample();
+        let split = round_trip(ListFormat::Json, None);
+        for list in ListName::ALL {
+            assert_eq!(list.get(&split), list.get(&src), "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn replace_clears_only_lists_in_file() {
+        let mut split = sample();
+        let content = r#"{ "format": "ultunnel-split-lists", "proxyDomains": ["new.example"] }"#;
+        let report = import(
+            &mut split,
+            content,
+            ListFormat::Json,
+            ImportMode::Replace,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(report.added, 1);
+        assert_eq!(split.proxy_domains, strings(&["new.example"]));
+        assert_eq!(split.bypass_domains, sample().bypass_domains);
+        assert_eq!(split.block_domains, sample().block_domains);
+        assert_eq!(split.proxy_apps, sample().proxy_apps);
+        assert_eq!(split.bypass_apps, sample().bypass_apps);
+
+        // в Clash заменяются только списки, в которые попало хотя бы одно правило
+        let content = "rules:\n  - PROCESS-NAME,curl,DIRECT\n";
+        import(
+            &mut split,
+            content,
+            ListFormat::Clash,
+            ImportMode::Replace,
+            None,
+        )
+        .unwrap();
+        assert_eq!(split.bypass_apps, strings(&["curl"]));
+        assert_eq!(split.proxy_apps, sample().proxy_apps);
+        assert_eq!(split.proxy_domains, strings(&["new.example"]));
+    }
+
+    #[test]
+    fn merge_reports_duplicates_and_conflicts() {
+        let mut split = sample();
+        let report = import(
+            &mut split,
+            "youtube.com\nexample.ru\nnew.example\n",
+            ListFormat::Text,
+            ImportMode::Merge,
+            Some(ListName::ProxyDomains),
+        )
+        .unwrap();
+
+        assert_eq!(report.added, 2);
+        assert_eq!(report.duplicates, strings(&["youtube.com"]));
+        assert_eq!(
+            report.conflicts,
+            strings(&["example.ru: через прокси и напрямую"])
+        );
+    }
+
+    #[test]
+    fn clash_policies_map_to_lists() {
+        let content = "\
+payload:
+  - DOMAIN-SUFFIX,direct.example,DIRECT
+  - DOMAIN,ads.example,REJECT
+  - DOMAIN-KEYWORD,track,REJECT-DROP
+  - 'DOMAIN-SUFFIX,.proxied.example,Proxy Group'
+  - PROCESS-NAME,curl,DIRECT
+  - PROCESS-PATH,/usr/bin/wget,PROXY
+  - PROCESS-NAME,evil,REJECT
+  - IP-CIDR,10.0.0.0/8,DIRECT
+  - DOMAIN-SUFFIX,provider.example
+";
+        let mut split = SplitRoutingSettings::default();
+        let report = import(
+            &mut split,
+            content,
+            ListFormat::Clash,
+            ImportMode::Merge,
+            Some(ListName::BypassDomains),
+        )
+        .unwrap();
+
+        assert_eq!(
+            split.bypass_domains,
+            strings(&["direct.example", "provider.example"])
+        );
+        assert_eq!(
+            split.block_domains,
+            strings(&["full:ads.example", "keyword:track"])
+        );
+        assert_eq!(split.proxy_domains, strings(&["proxied.example"]));
+        assert_eq!(split.bypass_apps, strings(&["curl"]));
+        assert_eq!(split.proxy_apps, strings(&["/usr/bin/wget"]));
+        // блокировки приложений и правил по IP в списках нет
+        assert_eq!(report.skipped.len(), 2, "{:?}", report.skipped);
+    }
+
+    #[test]
+    fn rule_set_dotted_suffix_becomes_regex() {
+        let content = r#"{
+            "version": 2,
+            "rules": [{ "domain_suffix": [".example.com", "example.org"] }]
+        }"#;
+        let mut split = SplitRoutingSettings::default();
+        import(
+            &mut split,
+            content,
+            ListFormat::RuleSet,
+            ImportMode::Merge,
+            Some(ListName::BypassDomains),
+        )
+        .unwrap();
+
+        assert_eq!(
+            split.bypass_domains,
+            strings(&[r"regex:\.example\.com$", "example.org"])
+        );
+        let re = regex::Regex::new(r"\.example\.com$").unwrap();
+        assert!(re.is_match("www.example.com"));
+        assert!(!re.is_match("example.com"));
+    }
+}
@@ -0,0 +1,189 @@
+// Работа туннеля как сервиса systemd: sd_notify (READY/WATCHDOG/STOPPING) и
+// генерация unit-файлов. Сам цикл сервиса — команда `ultunnel daemon` (см. daemon.rs).
+
+use std::env;
+use std::fs;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use tracing::info;
+use tracing::warn;
+
+pub const UNIT_NAME: &str = "ultunnel.service";
+
+/// Сообщение менеджеру сервисов через $NOTIFY_SOCKET; вне systemd ничего не делает
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET").filter(|v| !v.is_empty()) else {
+        return;
+    };
+
+    let result = (|| -> std::io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        let bytes = path.as_encoded_bytes();
+        // '@' в начале — абстрактный сокет
+        let addr = match bytes.strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        warn!("sd_notify({}) не удался: {}", state.lines().next().unwrap_or(""), e);
+    }
+}
+
+/// Интервал пингов watchdog (половина WatchdogSec), если он включён для этого процесса
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Где лежит unit: системный в /etc/systemd/system, пользовательский в ~/.config/systemd/user
+pub fn unit_path(user: bool) -> Result<PathBuf, String> {
+    if !user {
+        return Ok(Path::new("/etc/systemd/system").join(UNIT_NAME));
+    }
+
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|v| !v.is_empty())
+                .map(|h| PathBuf::from(h).join(".config"))
+        })
+        .ok_or("Не удалось определить ~/.config")?;
+    Ok(config_dir.join("systemd/user").join(UNIT_NAME))
+}
+
+/// Бинарник CLI `ultunnel`: сам процесс, если это он, иначе рядом с приложением
+pub fn cli_binary() -> Result<PathBuf, String> {
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    if exe.file_name().and_then(|n| n.to_str()) == Some("ultunnel") {
+        return Ok(exe);
+    }
+    let p = exe
+        .parent()
+        .ok_or("no exe dir".to_string())?
+        .join("ultunnel");
+    if p.exists() {
+        Ok(p)
+    } else {
+        Err(format!("ultunnel not found: {}", p.display()))
+    }
+}
+
+fn quote_arg(arg: &str) -> String {
+    if arg.chars().any(|c| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+pub fn render_unit(user: bool, cli: &Path, data_dir: &Path) -> String {
+    let exec = [
+        cli.to_string_lossy().to_string(),
+        "--data-dir".to_string(),
+        data_dir.to_string_lossy().to_string(),
+        "daemon".to_string(),
+    ]
+    .iter()
+    .map(|a| quote_arg(a))
+    .collect::<Vec<_>>()
+    .join(" ");
+
+    let (comment, wanted_by) = if user {
+        (
+            "# Пользовательский сервис: для TUN sing-box нужен CAP_NET_ADMIN\n\
+             # (например, setcap cap_net_admin,cap_net_bind_service+ep <путь к sing-box>)\n",
+            "default.target",
+        )
+    } else {
+        ("", "multi-user.target")
+    };
+
+    format!(
+        "{comment}[Unit]\n\
+         Description=ULtunnel (sing-box)\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         NotifyAccess=main\n\
+         ExecStart={exec}\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         WatchdogSec=30\n\
+         TimeoutStartSec=30\n\
+         TimeoutStopSec=15\n\
+         \n\
+         [Install]\n\
+         WantedBy={wanted_by}\n"
+    )
+}
+
+fn systemctl(user: bool, args: &[&str]) -> Result<(), String> {
+    let mut cmd = Command::new("systemctl");
+    if user {
+        cmd.arg("--user");
+    }
+    let out = cmd
+        .args(args)
+        .output()
+        .map_err(|e| format!("Не удалось запустить systemctl: {e}"))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "systemctl {} завершился с ошибкой: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))
+    }
+}
+
+/// Записывает unit-файл и перечитывает конфигурацию systemd; `enable` — сразу включить и запустить
+pub fn install_unit(user: bool, data_dir: &Path, enable: bool) -> Result<PathBuf, String> {
+    let path = unit_path(user)?;
+    let unit = render_unit(user, &cli_binary()?, data_dir);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, unit).map_err(|e| format!("Не удалось записать {}: {}", path.display(), e))?;
+    info!("Unit-файл systemd записан: {}", path.display());
+
+    systemctl(user, &["daemon-reload"])?;
+    if enable {
+        systemctl(user, &["enable", "--now", UNIT_NAME])?;
+    }
+    Ok(path)
+}
+
+pub fn uninstall_unit(user: bool) -> Result<PathBuf, String> {
+    let path = unit_path(user)?;
+    if !path.exists() {
+        return Err(format!("Unit-файл не найден: {}", path.display()));
+    }
+
+    if let Err(e) = systemctl(user, &["disable", "--now", UNIT_NAME]) {
+        warn!("{}", e);
+    }
+    fs::remove_file(&path).map_err(|e| e.to_string())?;
+    systemctl(user, &["daemon-reload"])?;
+    info!("Unit-файл systemd удалён: {}", path.display());
+    Ok(path)
+}
@@ -0,0 +1,259 @@
+use crate::clash_api;
+use crate::clash_api::JsonLines;
+use crate::quota;
+use crate::AppState;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::AppHandle;
+use tauri::Emitter;
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Коэффициент экспоненциального сглаживания скорости (0..1, больше — резче реакция)
+const RATE_SMOOTHING: f64 = 0.4;
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Событие `traffic-tick`, отправляемое в UI раз в секунду, пока Clash API доступен.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficTick {
+    pub up_bps: i64,
+    pub down_bps: i64,
+    pub active_connections: usize,
+    pub memory_mb: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrafficLine {
+    up: i64,
+    down: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MemoryLine {
+    inuse: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConnectionsResponse {
+    connections: Option<Vec<Connection>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection {
+    id: String,
+    #[serde(default)]
+    upload: u64,
+    #[serde(default)]
+    download: u64,
+    #[serde(default)]
+    metadata: ConnectionMetadata,
+    #[serde(default)]
+    rule: String,
+    #[serde(default)]
+    rule_payload: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionMetadata {
+    // заполняется ядром только при route.find_process = true
+    #[serde(default)]
+    process_path: String,
+}
+
+impl Connection {
+    fn process_key(&self) -> &str {
+        let p = self.metadata.process_path.trim();
+        if p.is_empty() {
+            "unknown"
+        } else {
+            p
+        }
+    }
+
+    fn rule_key(&self) -> String {
+        let rule = if self.rule.trim().is_empty() { "final" } else { self.rule.trim() };
+        if self.rule_payload.trim().is_empty() {
+            rule.to_string()
+        } else {
+            format!("{} ({})", rule, self.rule_payload.trim())
+        }
+    }
+}
+
+#[derive(Default)]
+struct Smoothed {
+    up: Option<f64>,
+    down: Option<f64>,
+}
+
+impl Smoothed {
+    fn push(&mut self, up: i64, down: i64) {
+        self.up = Some(smooth(self.up, up as f64));
+        self.down = Some(smooth(self.down, down as f64));
+    }
+}
+
+fn smooth(prev: Option<f64>, value: f64) -> f64 {
+    match prev {
+        Some(p) => p + RATE_SMOOTHING * (value - p),
+        None => value,
+    }
+}
+
+/// Долгоживущая подписка на `/traffic` и `/memory` Clash API.
+/// Пока ядро не запущено, подписка периодически переподключается.
+pub fn spawn_traffic_monitor(app: AppHandle, state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            match subscribe(&client, &app, &state).await {
+                Ok(()) => info!("Подписка на статистику Clash API завершена"),
+                Err(e) => debug!("Clash API недоступен для статистики: {}", e),
+            }
+
+            let was_active = {
+                let mut latest = state.traffic.lock().unwrap();
+                let active = latest.up_bps != 0 || latest.down_bps != 0 || latest.memory_mb != 0;
+                *latest = TrafficTick::default();
+                active
+            };
+            if was_active {
+                emit_tick(&app, &TrafficTick::default());
+            }
+
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    });
+}
+
+async fn subscribe(
+    client: &reqwest::Client,
+    app: &AppHandle,
+    state: &Arc<AppState>,
+) -> Result<(), String> {
+    let mut traffic = JsonLines::open(client, "/traffic").await?;
+    let mut memory = JsonLines::open(client, "/memory").await?;
+    info!("Подписка на /traffic и /memory Clash API установлена");
+
+    let profile = state.settings.lock().unwrap().selected_config.clone();
+    state.usage.lock().unwrap().begin_session(profile);
+    let result = read_streams(client, app, state, &mut traffic, &mut memory).await;
+    if let Err(e) = state.usage.lock().unwrap().end_session() {
+        error!("Ошибка сохранения traffic-usage.json: {}", e);
+    }
+    result
+}
+
+async fn read_streams(
+    client: &reqwest::Client,
+    app: &AppHandle,
+    state: &Arc<AppState>,
+    traffic: &mut JsonLines,
+    memory: &mut JsonLines,
+) -> Result<(), String> {
+    let mut smoothed = Smoothed::default();
+    let mut memory_mb = 0u64;
+    // байты, уже учтённые по каждому открытому соединению
+    let mut seen: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            line = traffic.next::<TrafficLine>() => match line {
+                Some(Ok(t)) => {
+                    smoothed.push(t.up, t.down);
+                    let profile = state.settings.lock().unwrap().selected_config.clone();
+                    state.usage.lock().unwrap().record(
+                        profile.as_deref(),
+                        t.up.max(0) as u64,
+                        t.down.max(0) as u64,
+                    );
+                }
+                Some(Err(e)) => warn!("/traffic: {}", e),
+                None => return Ok(()),
+            },
+            line = memory.next::<MemoryLine>() => match line {
+                Some(Ok(m)) => memory_mb = m.inuse / 1024 / 1024,
+                Some(Err(e)) => warn!("/memory: {}", e),
+                None => return Ok(()),
+            },
+            _ = ticker.tick() => {
+                let connections = fetch_connections(client).await;
+                if let Some(connections) = &connections {
+                    record_connections(state, connections, &mut seen);
+                }
+
+                let tick = TrafficTick {
+                    up_bps: smoothed.up.unwrap_or(0.0).round() as i64,
+                    down_bps: smoothed.down.unwrap_or(0.0).round() as i64,
+                    active_connections: connections.map(|c| c.len()).unwrap_or(0),
+                    memory_mb,
+                };
+                *state.traffic.lock().unwrap() = tick.clone();
+                emit_tick(app, &tick);
+
+                if let Err(e) = state.usage.lock().unwrap().flush_if_due() {
+                    error!("Ошибка сохранения traffic-usage.json: {}", e);
+                }
+                quota::check(app, state);
+            }
+        }
+    }
+}
+
+async fn fetch_connections(client: &reqwest::Client) -> Option<Vec<Connection>> {
+    let resp = client
+        .get(clash_api::url("/connections"))
+        .bearer_auth(clash_api::CLASH_API_SECRET)
+        .send()
+        .await;
+
+    match resp {
+        Ok(r) => r
+            .json::<ConnectionsResponse>()
+            .await
+            .ok()
+            .map(|c| c.connections.unwrap_or_default()),
+        Err(_) => None,
+    }
+}
+
+/// Разбивка по процессам и правилам: учитываем прирост байтов каждого соединения
+/// с прошлого опроса. Байты, переданные между последним опросом и закрытием
+/// соединения, не попадают в статистику.
+fn record_connections(
+    state: &Arc<AppState>,
+    connections: &[Connection],
+    seen: &mut HashMap<String, (u64, u64)>,
+) {
+    let mut usage = state.usage.lock().unwrap();
+    let mut current = HashMap::with_capacity(connections.len());
+
+    for c in connections {
+        let (prev_up, prev_down) = seen.get(&c.id).copied().unwrap_or((0, 0));
+        usage.record_connection(
+            c.process_key(),
+            &c.rule_key(),
+            c.upload.saturating_sub(prev_up),
+            c.download.saturating_sub(prev_down),
+        );
+        current.insert(c.id.clone(), (c.upload, c.download));
+    }
+
+    *seen = current;
+}
+
+fn emit_tick(app: &AppHandle, tick: &TrafficTick) {
+    if let Err(e) = app.emit("traffic-tick", tick) {
+        warn!("Не удалось отправить событие traffic-tick: {}", e);
+    }
+}
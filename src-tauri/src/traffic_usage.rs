@@ -0,0 +1,336 @@
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Как часто сбрасывать накопленные счётчики на диск во время сессии
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// Сколько дней хранить подневную статистику (помесячная хранится всегда)
+const DAYS_RETENTION: usize = 400;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageCounter {
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl UsageCounter {
+    fn add(&mut self, upload: u64, download: u64) {
+        self.upload = self.upload.saturating_add(upload);
+        self.download = self.download.saturating_add(download);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+}
+
+/// Счётчики за период: общий итог и разбивка по профилям, процессам и правилам
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageBucket {
+    #[serde(flatten)]
+    pub total: UsageCounter,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, UsageCounter>,
+
+    // по данным /connections: ключ — путь к процессу / текст сработавшего правила
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub processes: BTreeMap<String, UsageCounter>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<String, UsageCounter>,
+}
+
+impl UsageBucket {
+    /// Трафик профиля (`Some`) или общий (`None`) за период
+    pub fn bytes(&self, profile: Option<&str>) -> u64 {
+        match profile {
+            Some(p) => self.profiles.get(p).map(|c| c.total()).unwrap_or(0),
+            None => self.total.total(),
+        }
+    }
+
+    fn add(&mut self, profile: Option<&str>, upload: u64, download: u64) {
+        self.total.add(upload, download);
+        if let Some(p) = profile {
+            self.profiles
+                .entry(p.to_string())
+                .or_default()
+                .add(upload, download);
+        }
+    }
+
+    fn add_connection(&mut self, process: &str, rule: &str, upload: u64, download: u64) {
+        self.processes
+            .entry(process.to_string())
+            .or_default()
+            .add(upload, download);
+        self.rules
+            .entry(rule.to_string())
+            .or_default()
+            .add(upload, download);
+    }
+}
+
+/// Содержимое traffic-usage.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageFile {
+    #[serde(default)]
+    total: UsageBucket,
+    // ключ — YYYY-MM-DD
+    #[serde(default)]
+    days: BTreeMap<String, UsageBucket>,
+    // ключ — YYYY-MM
+    #[serde(default)]
+    months: BTreeMap<String, UsageBucket>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionUsage {
+    pub profile: Option<String>,
+    pub started_at: String,
+    #[serde(flatten)]
+    pub usage: UsageCounter,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodUsage {
+    pub period: String,
+    #[serde(flatten)]
+    pub usage: UsageBucket,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedUsage {
+    pub key: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub usage: UsageCounter,
+}
+
+/// Разбивка трафика по приложениям и правилам за последние N дней
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppTrafficReport {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub processes: Vec<NamedUsage>,
+    pub rules: Vec<NamedUsage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficUsageReport {
+    pub session: Option<SessionUsage>,
+    pub total: UsageBucket,
+    pub daily: Vec<PeriodUsage>,
+    pub monthly: Vec<PeriodUsage>,
+}
+
+/// Учёт переданного через туннель трафика (по сессии, дням, месяцам и профилям).
+/// Данные приходят из потока `/traffic` Clash API: каждая строка — байты за последнюю секунду.
+pub struct TrafficUsage {
+    path: PathBuf,
+    data: UsageFile,
+    session: Option<SessionUsage>,
+    last_flush: Instant,
+    dirty: bool,
+}
+
+pub fn day_key() -> String {
+    Local::now().format("%Y-%m-%d").to_string()
+}
+
+pub fn month_key() -> String {
+    Local::now().format("%Y-%m").to_string()
+}
+
+impl TrafficUsage {
+    pub fn load(path: &Path) -> Self {
+        let data = fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<UsageFile>(&s).ok())
+            .unwrap_or_default();
+
+        Self {
+            path: path.to_path_buf(),
+            data,
+            session: None,
+            last_flush: Instant::now(),
+            dirty: false,
+        }
+    }
+
+    pub fn begin_session(&mut self, profile: Option<String>) {
+        self.session = Some(SessionUsage {
+            profile,
+            started_at: Local::now().to_rfc3339(),
+            usage: UsageCounter::default(),
+        });
+    }
+
+    pub fn end_session(&mut self) -> Result<(), String> {
+        self.session = None;
+        self.flush()
+    }
+
+    pub fn record(&mut self, profile: Option<&str>, upload: u64, download: u64) {
+        if upload == 0 && download == 0 {
+            return;
+        }
+
+        if let Some(session) = self.session.as_mut() {
+            session.usage.add(upload, download);
+        }
+
+        self.data.total.add(profile, upload, download);
+        self.data
+            .days
+            .entry(day_key())
+            .or_default()
+            .add(profile, upload, download);
+        self.data
+            .months
+            .entry(month_key())
+            .or_default()
+            .add(profile, upload, download);
+        self.dirty = true;
+    }
+
+    /// Прирост байтов одного соединения Clash API с прошлого опроса
+    pub fn record_connection(&mut self, process: &str, rule: &str, upload: u64, download: u64) {
+        if upload == 0 && download == 0 {
+            return;
+        }
+
+        self.data.total.add_connection(process, rule, upload, download);
+        self.data
+            .days
+            .entry(day_key())
+            .or_default()
+            .add_connection(process, rule, upload, download);
+        self.data
+            .months
+            .entry(month_key())
+            .or_default()
+            .add_connection(process, rule, upload, download);
+        self.dirty = true;
+    }
+
+    /// Периодический сброс на диск, вызывается из подписки на трафик
+    pub fn flush_if_due(&mut self) -> Result<(), String> {
+        if self.last_flush.elapsed() < FLUSH_INTERVAL {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.last_flush = Instant::now();
+        if !self.dirty {
+            return Ok(());
+        }
+
+        while self.data.days.len() > DAYS_RETENTION {
+            self.data.days.pop_first();
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let s = serde_json::to_string_pretty(&self.data).map_err(|e| e.to_string())?;
+        fs::write(&self.path, s).map_err(|e| e.to_string())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> Result<(), String> {
+        self.data = UsageFile::default();
+        if let Some(session) = self.session.as_mut() {
+            session.usage = UsageCounter::default();
+        }
+        self.dirty = true;
+        self.flush()
+    }
+
+    pub fn today(&self) -> UsageBucket {
+        self.data.days.get(&day_key()).cloned().unwrap_or_default()
+    }
+
+    pub fn this_month(&self) -> UsageBucket {
+        self.data.months.get(&month_key()).cloned().unwrap_or_default()
+    }
+
+    pub fn app_report(&self, days: usize) -> AppTrafficReport {
+        let mut processes: BTreeMap<String, UsageCounter> = BTreeMap::new();
+        let mut rules: BTreeMap<String, UsageCounter> = BTreeMap::new();
+        let mut from = None;
+        let mut to = None;
+
+        for (day, bucket) in self.data.days.iter().rev().take(days.max(1)) {
+            to.get_or_insert_with(|| day.clone());
+            from = Some(day.clone());
+            for (k, v) in &bucket.processes {
+                processes.entry(k.clone()).or_default().add(v.upload, v.download);
+            }
+            for (k, v) in &bucket.rules {
+                rules.entry(k.clone()).or_default().add(v.upload, v.download);
+            }
+        }
+
+        AppTrafficReport {
+            from,
+            to,
+            processes: sorted_by_usage(processes, |k| {
+                Path::new(k)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| k.to_string())
+            }),
+            rules: sorted_by_usage(rules, |k| k.to_string()),
+        }
+    }
+
+    pub fn report(&self, days: usize, months: usize) -> TrafficUsageReport {
+        let last = |map: &BTreeMap<String, UsageBucket>, n: usize| -> Vec<PeriodUsage> {
+            map.iter()
+                .rev()
+                .take(n)
+                .map(|(k, v)| PeriodUsage {
+                    period: k.clone(),
+                    usage: v.clone(),
+                })
+                .collect()
+        };
+
+        TrafficUsageReport {
+            session: self.session.clone(),
+            total: self.data.total.clone(),
+            daily: last(&self.data.days, days),
+            monthly: last(&self.data.months, months),
+        }
+    }
+}
+
+fn sorted_by_usage(map: BTreeMap<String, UsageCounter>, name: impl Fn(&str) -> String) -> Vec<NamedUsage> {
+    let mut out: Vec<NamedUsage> = map
+        .into_iter()
+        .map(|(key, usage)| NamedUsage {
+            name: name(&key),
+            key,
+            usage,
+        })
+        .collect();
+    out.sort_by_key(|e| std::cmp::Reverse(e.usage.total()));
+    out
+}
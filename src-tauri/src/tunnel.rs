@@ -0,0 +1,303 @@
+// Запуск и остановка sing-box без привязки к UI: общий код приложения (Linux) и CLI `ultunnel`.
+// Запущенное ядро отмечается pid-файлом в папке данных, поэтому CLI видит ядро,
+// запущенное приложением, и наоборот.
+
+use crate::api::ProxyConfig;
+use crate::clash_api;
+#[cfg(target_os = "linux")]
+use crate::kill_switch;
+use crate::rule_sets;
+use crate::settings::LocalSettings;
+use crate::singbox_config;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::time::Duration;
+#[cfg(unix)]
+use std::time::Instant;
+use tracing::info;
+#[cfg(unix)]
+use tracing::warn;
+
+/// Идентификатор приложения из tauri.conf.json — имя папки данных
+pub const APP_IDENTIFIER: &str = "ru.ravel.ultunnel-macos";
+
+const PID_FILE: &str = "singbox.pid";
+const CLASH_API_TIMEOUT_MS: u64 = 5000;
+#[cfg(unix)]
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Папка данных приложения (совпадает с app_data_dir у Tauri): config.json, configs.json, singbox.json.
+/// ULTUNNEL_DATA_DIR переопределяет путь.
+pub fn default_data_dir() -> Result<PathBuf, String> {
+    if let Some(dir) = env::var_os("ULTUNNEL_DATA_DIR").filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let base = platform_data_dir().ok_or("Не удалось определить папку данных приложения")?;
+    Ok(base.join(APP_IDENTIFIER))
+}
+
+pub fn settings_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("config.json")
+}
+
+#[cfg(target_os = "windows")]
+fn platform_data_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_data_dir() -> Option<PathBuf> {
+    home_dir().map(|h| h.join("Library/Application Support"))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn platform_data_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| home_dir().map(|h| h.join(".local/share")))
+}
+
+#[cfg(unix)]
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+#[cfg(target_os = "linux")]
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// sing-box рядом с исполняемым файлом (так его раскладывает сборка Tauri) или из ULTUNNEL_SINGBOX
+pub fn singbox_binary() -> Result<PathBuf, String> {
+    if let Some(p) = env::var_os("ULTUNNEL_SINGBOX").filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(p));
+    }
+
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let dir = exe.parent().ok_or("no exe dir".to_string())?;
+    let p = dir.join(if cfg!(windows) { "sing-box.exe" } else { "sing-box" });
+    if p.exists() {
+        Ok(p)
+    } else {
+        Err(format!("sing-box not found: {}", p.display()))
+    }
+}
+
+pub fn singbox_log_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("logs").join("sing-box.log")
+}
+
+#[cfg(unix)]
+fn spawn_singbox(data_dir: &Path, cfg_path: &Path) -> Result<Child, String> {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use std::process::Stdio;
+
+    let bin = singbox_binary()?;
+    let log_path = singbox_log_path(data_dir);
+    if let Some(dir) = log_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let log = fs::File::create(&log_path).map_err(|e| e.to_string())?;
+    let log_err = log.try_clone().map_err(|e| e.to_string())?;
+
+    let child = Command::new(&bin)
+        .arg("run")
+        .arg("-c")
+        .arg(cfg_path)
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(log_err)
+        // своя группа процессов: Ctrl+C в терминале CLI не должен останавливать туннель
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("Не удалось запустить sing-box: {e}"))?;
+
+    fs::write(data_dir.join(PID_FILE), child.id().to_string()).map_err(|e| e.to_string())?;
+    info!("sing-box запущен, pid={}", child.id());
+    Ok(child)
+}
+
+#[cfg(not(unix))]
+fn spawn_singbox(_data_dir: &Path, _cfg_path: &Path) -> Result<Child, String> {
+    Err("Запуск sing-box без приложения поддержан только на Linux и macOS".to_string())
+}
+
+/// pid запущенного ядра из pid-файла, если процесс ещё жив
+#[cfg(unix)]
+pub fn running_pid(data_dir: &Path) -> Option<u32> {
+    let pid = fs::read_to_string(data_dir.join(PID_FILE))
+        .ok()?
+        .trim()
+        .parse::<u32>()
+        .ok()?;
+
+    let alive = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    (alive && is_singbox_process(pid, data_dir)).then_some(pid)
+}
+
+#[cfg(not(unix))]
+pub fn running_pid(_data_dir: &Path) -> Option<u32> {
+    None
+}
+
+/// Отсекаем зомби и чужой процесс с переиспользованным pid: в командной строке ядра
+/// всегда есть путь к конфигу в папке данных
+#[cfg(target_os = "linux")]
+fn is_singbox_process(pid: u32, data_dir: &Path) -> bool {
+    let Ok(stat) = fs::read_to_string(format!("/proc/{pid}/stat")) else {
+        return false;
+    };
+    let zombie = stat
+        .rsplit_once(") ")
+        .map(|(_, rest)| rest.starts_with('Z'))
+        .unwrap_or(true);
+    if zombie {
+        return false;
+    }
+
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+    String::from_utf8_lossy(&cmdline).contains(&*data_dir.to_string_lossy())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_singbox_process(_pid: u32, _data_dir: &Path) -> bool {
+    true
+}
+
+/// Останавливает ядро из pid-файла: SIGTERM, по таймауту SIGKILL.
+/// `false` — ядро не было запущено.
+#[cfg(unix)]
+pub fn stop_singbox(data_dir: &Path) -> Result<bool, String> {
+    let pid_path = data_dir.join(PID_FILE);
+    let Some(pid) = running_pid(data_dir) else {
+        let _ = fs::remove_file(&pid_path);
+        return Ok(false);
+    };
+
+    send_signal(pid, libc::SIGTERM)?;
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while running_pid(data_dir).is_some() {
+        if Instant::now() >= deadline {
+            warn!("sing-box (pid {}) не завершился за {:?}, SIGKILL", pid, STOP_TIMEOUT);
+            send_signal(pid, libc::SIGKILL)?;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let _ = fs::remove_file(&pid_path);
+    info!("sing-box остановлен, pid={}", pid);
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn stop_singbox(_data_dir: &Path) -> Result<bool, String> {
+    Err("Остановка sing-box без приложения поддержана только на Linux и macOS".to_string())
+}
+
+/// stop_singbox для async-кода: ожидание завершения ядра не занимает поток рантайма
+pub async fn stop_singbox_async(data_dir: &Path) -> Result<bool, String> {
+    let data_dir = data_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || stop_singbox(&data_dir))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: libc::c_int) -> Result<(), String> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "Не удалось остановить sing-box (pid {}): {}",
+            pid,
+            std::io::Error::last_os_error()
+        ))
+    }
+}
+
+/// Запуск ядра с готовым конфигом и ожидание Clash API; при неудаче процесс останавливается.
+/// Дочерний процесс возвращается вызывающему: приложение его дожидается, CLI отпускает в фон.
+pub async fn launch(data_dir: &Path, cfg_path: &Path) -> Result<Child, String> {
+    let mut child = spawn_singbox(data_dir, cfg_path)?;
+    if clash_api::wait_ready(CLASH_API_TIMEOUT_MS).await {
+        return Ok(child);
+    }
+
+    let exited = matches!(child.try_wait(), Ok(Some(_)));
+    let _ = stop_singbox_async(data_dir).await;
+    let _ = child.kill();
+    let _ = child.wait();
+
+    if exited {
+        Err(format!(
+            "sing-box завершился при запуске, см. {}",
+            singbox_log_path(data_dir).display()
+        ))
+    } else {
+        Err("sing-box запустился, но Clash API на 127.0.0.1:9090 не ответил".into())
+    }
+}
+
+/// Подключение к профилю: singbox.json, правила kill switch (Linux) и запуск ядра
+pub async fn start(data_dir: &Path, cfg: &ProxyConfig, settings: &LocalSettings) -> Result<Child, String> {
+    // удалённые rule-set'ы подключаются из кэша: без копии скачиваем до генерации конфига,
+    // устаревшие обновит фоновая задача
+    rule_sets::fetch_missing(data_dir, &settings.split_routing_for(Some(&cfg.name))).await;
+    let cfg_path =
+        singbox_config::write_singbox_config(data_dir, &cfg.config, settings, Some(&cfg.name))?;
+
+    // правила ставятся до запуска ядра и остаются, даже если sing-box упадёт
+    #[cfg(target_os = "linux")]
+    if settings.kill_switch_enabled && settings.connected_intent {
+        let mut v = cfg.config.clone();
+        let tun_interfaces = kill_switch::ensure_tun_interface_names(&mut v);
+        kill_switch::enable(data_dir, &v, &tun_interfaces).await?;
+    }
+
+    launch(data_dir, &cfg_path).await
+}
+
+/// Внешний IP, видимый через поднятый туннель (проверка профилей)
+pub async fn fetch_external_ip() -> Result<String, String> {
+    // Здесь намеренно НЕ используется reqwest::Proxy.
+    // Проверка должна идти как обычный системный трафик приложения после поднятия TUN,
+    // чтобы IP был получен через полноценный VPN-маршрут, а не через локальный порт.
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(20))
+        .no_proxy()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let resp = client
+        .get("https://ipinfo.io/ip")
+        .header(reqwest::header::USER_AGENT, "ultunnel-desktop")
+        .send()
+        .await
+        .map_err(|e| format!("ipinfo request through full VPN failed: {}", e))?;
+
+    if !resp.status().is_success() {
+        return Err(format!("ipinfo through full VPN returned HTTP {}", resp.status()));
+    }
+
+    let ip = resp
+        .text()
+        .await
+        .map_err(|e| format!("ipinfo response through full VPN read failed: {}", e))?
+        .trim()
+        .to_string();
+
+    if ip.is_empty() {
+        Err("ipinfo through full VPN returned empty IP".to_string())
+    } else {
+        Ok(ip)
+    }
+}